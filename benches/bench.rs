@@ -76,7 +76,20 @@ fn bench_parse_vec(c: &mut Criterion) {
         let f = std::fs::read("benches/fixture.wasm").unwrap();
         b.iter(|| {
             let f = black_box(f.as_slice());
-            unlazify(Module::decode_from(f).unwrap())
+            unlazify(Module::decode_from_slice(f).unwrap())
+        })
+    });
+}
+
+#[cfg(feature = "rayon")]
+fn bench_parse_par(c: &mut Criterion) {
+    c.bench_function(concat!(stringify!($name), "::bench_parse_par"), |b| {
+        let f = std::fs::read("benches/fixture.wasm").unwrap();
+        b.iter(|| {
+            let f = black_box(f.as_slice());
+            let m = Module::decode_from_slice(f).unwrap();
+            m.par_decode_func_bodies().unwrap();
+            unlazify(m)
         })
     });
 }
@@ -88,7 +101,7 @@ fn bench_parse_deep_module(c: &mut Criterion) {
             let f = deep_module().encode_into(Vec::new()).unwrap();
             b.iter(|| {
                 let f = black_box(f.as_slice());
-                unlazify(Module::decode_from(f).unwrap())
+                unlazify(Module::decode_from_slice(f).unwrap())
             })
         },
     );
@@ -96,7 +109,7 @@ fn bench_parse_deep_module(c: &mut Criterion) {
 
 fn read_module() -> Module {
     let f = std::fs::read("benches/fixture.wasm").unwrap();
-    unlazify(Module::decode_from(f.as_slice()).unwrap()).unwrap()
+    unlazify(Module::decode_from_slice(&f).unwrap()).unwrap()
 }
 
 fn bench_write(c: &mut Criterion) {
@@ -151,4 +164,15 @@ criterion_group! {
         bench_write_deep_module,
 }
 
+#[cfg(feature = "rayon")]
+criterion_group! {
+    name = par_benches;
+    config = Criterion::default().sample_size(20);
+    targets = bench_parse_par,
+}
+
+#[cfg(feature = "rayon")]
+criterion_main!(benches, par_benches);
+
+#[cfg(not(feature = "rayon"))]
 criterion_main!(benches);
@@ -20,7 +20,7 @@ use synstructure::{decl_derive, Structure, VariantInfo};
 
 macro_rules! syn_throw {
     ($err:expr) => {
-        return syn::Error::to_compile_error(&$err);
+        return syn::Error::to_compile_error(&$err)
     };
 }
 
@@ -33,29 +33,79 @@ macro_rules! syn_try {
     };
 }
 
+enum WasmbinAttr {
+    Discriminant(syn::Expr),
+    Requires(syn::Expr),
+    Crate(syn::Path),
+}
+
+impl syn::parse::Parse for WasmbinAttr {
+    fn parse(parser: syn::parse::ParseStream) -> syn::Result<Self> {
+        syn::custom_keyword!(discriminant);
+        syn::custom_keyword!(requires);
+
+        let lookahead = parser.lookahead1();
+        if lookahead.peek(discriminant) {
+            parser.parse::<discriminant>()?;
+            parser.parse::<syn::Token![=]>()?;
+            parser.parse().map(WasmbinAttr::Discriminant)
+        } else if lookahead.peek(requires) {
+            parser.parse::<requires>()?;
+            parser.parse::<syn::Token![=]>()?;
+            parser.parse().map(WasmbinAttr::Requires)
+        } else if lookahead.peek(syn::Token![crate]) {
+            parser.parse::<syn::Token![crate]>()?;
+            parser.parse::<syn::Token![=]>()?;
+            parser.parse().map(WasmbinAttr::Crate)
+        } else {
+            Err(lookahead.error())
+        }
+    }
+}
+
+fn wasmbin_attrs<'a>(
+    attrs: &'a [syn::Attribute],
+) -> impl Iterator<Item = syn::Result<WasmbinAttr>> + 'a {
+    attrs.iter().filter_map(|attr| match attr {
+        syn::Attribute {
+            style: syn::AttrStyle::Outer,
+            path,
+            ..
+        } if path.is_ident("wasmbin") => Some(attr.parse_args()),
+        _ => None,
+    })
+}
+
+/// Path to the `wasmbin` crate, `::wasmbin` unless overridden via
+/// `#[wasmbin(crate = ...)]`.
+fn crate_path(s: &Structure) -> syn::Result<syn::Path> {
+    for attr in wasmbin_attrs(&s.ast().attrs) {
+        if let WasmbinAttr::Crate(path) = attr? {
+            return Ok(path);
+        }
+    }
+    Ok(syn::parse_quote!(::wasmbin))
+}
+
+fn required_features(v: &VariantInfo) -> syn::Result<Vec<syn::Expr>> {
+    let mut features = Vec::new();
+    for attr in wasmbin_attrs(v.ast().attrs) {
+        if let WasmbinAttr::Requires(feature) = attr? {
+            features.push(feature);
+        }
+    }
+    Ok(features)
+}
+
 fn discriminant<'v>(v: &VariantInfo<'v>) -> syn::Result<Option<Cow<'v, syn::Expr>>> {
     v.ast()
         .discriminant
         .iter()
         .map(|(_, discriminant)| Ok(Cow::Borrowed(discriminant)))
-        .chain(v.ast().attrs.iter().filter_map(|attr| match attr {
-            syn::Attribute {
-                style: syn::AttrStyle::Outer,
-                path,
-                ..
-            } if path.is_ident("wasmbin") => {
-                syn::custom_keyword!(discriminant);
-
-                Some(
-                    attr.parse_args_with(|parser: syn::parse::ParseStream| {
-                        parser.parse::<discriminant>()?;
-                        parser.parse::<syn::Token![=]>()?;
-                        parser.parse()
-                    })
-                    .map(Cow::Owned),
-                )
-            }
-            _ => None,
+        .chain(wasmbin_attrs(v.ast().attrs).filter_map(|attr| match attr {
+            Ok(WasmbinAttr::Discriminant(discriminant)) => Some(Ok(Cow::Owned(discriminant))),
+            Ok(WasmbinAttr::Requires(_) | WasmbinAttr::Crate(_)) => None,
+            Err(err) => Some(Err(err)),
         }))
         .try_fold(None, |prev, discriminant| {
             let discriminant = discriminant?;
@@ -78,6 +128,13 @@ fn gen_encode_discriminant(repr: &syn::Type, discriminant: &syn::Expr) -> proc_m
     quote!(<#repr as Encode>::encode(&#discriminant, w)?)
 }
 
+fn gen_encoded_len_discriminant(
+    repr: &syn::Type,
+    discriminant: &syn::Expr,
+) -> proc_macro2::TokenStream {
+    quote!(<#repr as Encode>::encoded_len(&#discriminant)?)
+}
+
 fn is_newtype_like(v: &VariantInfo) -> bool {
     matches!(v.ast().fields, fields @ syn::Fields::Unnamed(_) if fields.len() == 1)
 }
@@ -129,11 +186,37 @@ fn gen_decode(v: &VariantInfo) -> proc_macro2::TokenStream {
         let res = track_err_in_field(quote!(Decode::decode(r)), v, field, index);
         quote!(#res?)
     });
+    let features = syn_try!(required_features(v));
+    if !features.is_empty() {
+        res = quote!({ #(require_feature(&*r, #features)?;)* #res });
+    }
     res = catch_expr(res, quote!(DecodeError));
     res = track_err_in_variant(res, v);
     res
 }
 
+fn gen_encode(v: &VariantInfo) -> proc_macro2::TokenStream {
+    let mut res = quote!();
+    for (index, bi) in v.bindings().iter().enumerate() {
+        let field_res = track_err_in_field(quote!(Encode::encode(#bi, w)), v, bi.ast(), index);
+        (quote!(#field_res?;)).to_tokens(&mut res);
+    }
+    res = catch_expr(res, quote!(EncodeError));
+    res = track_err_in_variant(res, v);
+    res
+}
+
+fn gen_encoded_len(v: &VariantInfo) -> proc_macro2::TokenStream {
+    let mut res = quote!(0);
+    for (index, bi) in v.bindings().iter().enumerate() {
+        let field_res = track_err_in_field(quote!(Encode::encoded_len(#bi)), v, bi.ast(), index);
+        res = quote!(#res + #field_res?);
+    }
+    res = catch_expr(res, quote!(EncodeError));
+    res = track_err_in_variant(res, v);
+    res
+}
+
 fn parse_repr(s: &Structure) -> syn::Result<syn::Type> {
     s.ast()
         .attrs
@@ -148,12 +231,14 @@ fn parse_repr(s: &Structure) -> syn::Result<syn::Type> {
         .parse_args()
 }
 
-fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
-    let (encode_discriminant, decode) = match s.ast().data {
+fn wasmbin_derive(mut s: Structure) -> proc_macro2::TokenStream {
+    let krate = syn_try!(crate_path(&s));
+    let (encode_discriminant, encoded_len_discriminant, decode) = match s.ast().data {
         syn::Data::Enum(_) => {
             let repr = syn_try!(parse_repr(&s));
 
             let mut encode_discriminant = quote!();
+            let mut encoded_len_discriminant = quote!();
 
             let mut decoders = quote!();
             let mut decode_other = quote!({ return Ok(None) });
@@ -168,6 +253,9 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                         let encode = gen_encode_discriminant(&repr, &discriminant);
                         (quote!(#pat => #encode,)).to_tokens(&mut encode_discriminant);
 
+                        let encoded_len = gen_encoded_len_discriminant(&repr, &discriminant);
+                        (quote!(#pat => #encoded_len,)).to_tokens(&mut encoded_len_discriminant);
+
                         let decode = gen_decode(v);
                         (quote!(
                             #discriminant => #decode?,
@@ -206,12 +294,18 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                         _ => {}
                     }
                 },
+                quote! {
+                    match *self {
+                        #encoded_len_discriminant
+                        _ => 0
+                    }
+                },
                 quote! {
                     gen impl DecodeWithDiscriminant for @Self {
                         const NAME: &'static str = #name;
                         type Discriminant = #repr;
 
-                        fn maybe_decode_with_discriminant(discriminant: #repr, r: &mut impl std::io::Read) -> Result<Option<Self>, DecodeError> {
+                        fn maybe_decode_with_discriminant(discriminant: #repr, r: &mut impl #krate::io::Read) -> Result<Option<Self>, DecodeError> {
                             Ok(Some(match discriminant {
                                 #decoders
                                 _ => #decode_other
@@ -220,7 +314,7 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                     }
 
                     gen impl Decode for @Self {
-                        fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                        fn decode(r: &mut impl #krate::io::Read) -> Result<Self, DecodeError> {
                             DecodeWithDiscriminant::decode_without_discriminant(r)
                         }
                     }
@@ -237,12 +331,13 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                     let name = s.ast().ident.to_string();
                     (
                         gen_encode_discriminant(&syn::parse_quote!(u8), &discriminant),
+                        gen_encoded_len_discriminant(&syn::parse_quote!(u8), &discriminant),
                         quote! {
                             gen impl DecodeWithDiscriminant for @Self {
                                 const NAME: &'static str = #name;
                                 type Discriminant = u8;
 
-                                fn maybe_decode_with_discriminant(discriminant: u8, r: &mut impl std::io::Read) -> Result<Option<Self>, DecodeError> {
+                                fn maybe_decode_with_discriminant(discriminant: u8, r: &mut impl #krate::io::Read) -> Result<Option<Self>, DecodeError> {
                                     match discriminant {
                                         #discriminant => #decode.map(Some),
                                         _ => Ok(None),
@@ -251,7 +346,7 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                             }
 
                             gen impl Decode for @Self {
-                                fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                                fn decode(r: &mut impl #krate::io::Read) -> Result<Self, DecodeError> {
                                     DecodeWithDiscriminant::decode_without_discriminant(r)
                                 }
                             }
@@ -260,9 +355,10 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
                 }
                 None => (
                     quote! {},
+                    quote! { 0 },
                     quote! {
                         gen impl Decode for @Self {
-                            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+                            fn decode(r: &mut impl #krate::io::Read) -> Result<Self, DecodeError> {
                                 #decode
                             }
                         }
@@ -272,37 +368,51 @@ fn wasmbin_derive(s: Structure) -> proc_macro2::TokenStream {
         }
     };
 
-    let encode_body = s.each(|bi| {
-        quote! {
-            Encode::encode(#bi, w)?
-        }
-    });
+    let mut encode_body = quote!();
+    let mut encoded_len_body = quote!();
+    for v in s.variants() {
+        let pat = v.pat();
+        let encode = gen_encode(v);
+        (quote!(#pat => #encode?,)).to_tokens(&mut encode_body);
+        let encoded_len = gen_encoded_len(v);
+        (quote!(#pat => #encoded_len?,)).to_tokens(&mut encoded_len_body);
+    }
 
-    s.gen_impl(quote! {
-        use crate::io::{Encode, Decode, DecodeWithDiscriminant, DecodeError, PathItem};
+    s.underscore_const(true).gen_impl(quote! {
+        use #krate::io::{Encode, EncodeError, Decode, DecodeWithDiscriminant, DecodeError, PathItem, Feature, require_feature};
 
         gen impl Encode for @Self {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+            fn encode(&self, w: &mut impl #krate::io::Write) -> Result<(), EncodeError> {
                 #encode_discriminant;
                 match *self { #encode_body }
                 Ok(())
             }
+
+            fn encoded_len(&self) -> Result<usize, EncodeError> {
+                let discriminant_len = #encoded_len_discriminant;
+                Ok(discriminant_len + match *self { #encoded_len_body })
+            }
         }
 
         #decode
     })
 }
 
-fn wasmbin_countable_derive(s: Structure) -> proc_macro2::TokenStream {
-    s.gen_impl(quote! {
-        gen impl crate::builtins::WasmbinCountable for @Self {}
+fn wasmbin_countable_derive(mut s: Structure) -> proc_macro2::TokenStream {
+    let krate = syn_try!(crate_path(&s));
+    s.underscore_const(true).gen_impl(quote! {
+        gen impl #krate::builtins::WasmbinCountable for @Self {}
     })
 }
 
 fn wasmbin_visit_derive(mut s: Structure) -> proc_macro2::TokenStream {
+    let krate = syn_try!(crate_path(&s));
     s.bind_with(|_| synstructure::BindStyle::Move);
 
-    fn generate_visit_body(s: &Structure, method: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    fn generate_visit_body(
+        s: &Structure,
+        method: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
         let body = s.each_variant(|v| {
             let res = v.bindings().iter().enumerate().map(|(i, bi)| {
                 let res = quote!(Visit::#method(#bi, f));
@@ -323,9 +433,9 @@ fn wasmbin_visit_derive(mut s: Structure) -> proc_macro2::TokenStream {
 
     let visit_children_mut_body = generate_visit_body(&s, quote!(visit_child_mut));
 
-    s.gen_impl(quote! {
-        use crate::visit::{Visit, VisitError};
-        use crate::io::PathItem;
+    s.underscore_const(true).gen_impl(quote! {
+        use #krate::visit::{Visit, VisitError};
+        use #krate::io::PathItem;
 
         gen impl Visit for @Self where Self: 'static {
             fn visit_children<'a, VisitT: 'static, VisitE, VisitF: FnMut(&'a VisitT) -> Result<(), VisitE>>(&'a self, f: &mut VisitF) -> Result<(), VisitError<VisitE>> {
@@ -370,6 +480,68 @@ pub fn wasmbin_discriminants(
     input.into_token_stream().into()
 }
 
-decl_derive!([Wasmbin, attributes(wasmbin)] => wasmbin_derive);
-decl_derive!([WasmbinCountable] => wasmbin_countable_derive);
-decl_derive!([Visit] => wasmbin_visit_derive);
+decl_derive!([Wasmbin, attributes(wasmbin)] =>
+/// Derives `Encode` and `Decode` from `wasmbin::io`.
+///
+/// Structs are encoded as their fields in order. Enums are encoded as a
+/// discriminant followed by the fields of the matching variant.
+///
+/// Supported attributes:
+///
+/// - `#[repr(type)]` on enums (required): type of the discriminant, such as
+///   `u8` or `u32`, encoded with its own `Encode` implementation.
+/// - `= expr` or `#[wasmbin(discriminant = expr)]` on enum variants:
+///   discriminant of the variant.
+/// - `#[wasmbin(discriminant = expr)]` on structs: `u8` that must precede
+///   the fields. Such structs also implement `DecodeWithDiscriminant`.
+/// - Variants without a discriminant are catch-alls. They must have a single
+///   field implementing `DecodeWithDiscriminant`, which is tried when none
+///   of the other discriminants match, and which encodes the discriminant
+///   itself.
+/// - `#[wasmbin(requires = expr)]` on enum variants: `wasmbin::io::Feature`
+///   that must be enabled for the variant to be decoded. Can be repeated
+///   for variants that need several proposals.
+/// - `#[wasmbin(crate = path)]` on the type: path to the `wasmbin` crate,
+///   for when it isn't available as `::wasmbin`, e.g. when re-exported.
+///
+/// # Example
+///
+/// ```
+/// use wasmbin::builtins::WasmbinCountable;
+/// use wasmbin::io::{Decode, Encode, Wasmbin};
+///
+/// #[derive(Wasmbin, WasmbinCountable, Debug, PartialEq)]
+/// struct Entry {
+///     name: String,
+///     value: u32,
+/// }
+///
+/// #[derive(Wasmbin, Debug, PartialEq)]
+/// #[repr(u8)]
+/// enum Payload {
+///     Entries(Vec<Entry>) = 0,
+///     #[wasmbin(discriminant = 1)]
+///     Count { count: u32 },
+/// }
+///
+/// let payload = Payload::Entries(vec![Entry {
+///     name: "answer".to_owned(),
+///     value: 42,
+/// }]);
+/// let mut bytes = Vec::new();
+/// payload.encode(&mut bytes)?;
+/// assert_eq!(Payload::decode(&mut bytes.as_slice())?, payload);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+wasmbin_derive);
+decl_derive!([WasmbinCountable, attributes(wasmbin)] =>
+/// Derives `wasmbin::builtins::WasmbinCountable`, which allows encoding
+/// `Vec`s of the type as length-prefixed sequences.
+///
+/// Supports `#[wasmbin(crate = path)]` like `#[derive(Wasmbin)]`.
+wasmbin_countable_derive);
+decl_derive!([Visit, attributes(wasmbin)] =>
+/// Derives `wasmbin::visit::Visit` by visiting all the fields in order.
+///
+/// Supports `#[wasmbin(crate = path)]` like `#[derive(Wasmbin)]`.
+wasmbin_visit_derive);
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use std::fs::File;
-use std::io::{BufReader, Seek, SeekFrom};
+use std::io::BufReader;
 use structopt::StructOpt;
 use wasmbin::io::DecodeError;
 use wasmbin::sections::{Kind, Section};
@@ -29,6 +29,7 @@ enum DumpSection {
     Function,
     Table,
     Memory,
+    Tag,
     Global,
     Export,
     Start,
@@ -62,17 +63,24 @@ fn unlazify_with_opt<T: Visit>(wasm: &mut T, include_raw: bool) -> Result<(), De
     }
 }
 
+fn report(err: &DecodeError) -> ! {
+    match err.offset() {
+        Some(offset) => {
+            let path: String = err.path().map(ToString::to_string).collect();
+            panic!(
+                "Parsing error at offset 0x{:08X}: (root){}: {}",
+                offset, path, err.kind
+            )
+        }
+        None => panic!("Parsing error: {}", err),
+    }
+}
+
 fn main() {
     let opts = DumpOpts::from_args();
     let f = File::open(opts.filename).unwrap();
-    let mut f = BufReader::new(f);
-    let mut m = Module::decode_from(&mut f).unwrap_or_else(|err| {
-        panic!(
-            "Parsing error at offset 0x{:08X}: {}",
-            f.seek(SeekFrom::Current(0)).unwrap(),
-            err
-        )
-    });
+    let f = BufReader::new(f);
+    let mut m = Module::decode_from(f).unwrap_or_else(|err| report(&err));
     let filter: Box<dyn Fn(&Section) -> bool> = match opts.section {
         DumpSection::All => Box::new(|_s: &Section| true) as _,
         DumpSection::Custom { name } => Box::new(move |s: &Section| {
@@ -93,6 +101,7 @@ fn main() {
         DumpSection::Function => Box::new(|s| s.kind() == Kind::Function),
         DumpSection::Table => Box::new(|s| s.kind() == Kind::Table),
         DumpSection::Memory => Box::new(|s| s.kind() == Kind::Memory),
+        DumpSection::Tag => Box::new(|s| s.kind() == Kind::Tag),
         DumpSection::Global => Box::new(|s| s.kind() == Kind::Global),
         DumpSection::Export => Box::new(|s| s.kind() == Kind::Export),
         DumpSection::Start => Box::new(|s| s.kind() == Kind::Start),
@@ -104,7 +113,7 @@ fn main() {
     let mut count = 0;
     for s in m.sections.iter_mut().filter(|s| filter(s)) {
         count += 1;
-        unlazify_with_opt(s, opts.include_raw).unwrap();
+        unlazify_with_opt(s, opts.include_raw).unwrap_or_else(|err| report(&err));
         println!("{:#?}", s);
     }
     println!("Found {} sections.", count);
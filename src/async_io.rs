@@ -0,0 +1,215 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::builtins::{peek_usize, MAX_PREALLOC, MAX_USIZE_LEN};
+use crate::io::{
+    Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeOptions, Encode, EncodeError, Limit,
+    PathItem,
+};
+use crate::module::{MagicAndVersion, MAGIC_AND_VERSION};
+use crate::sections::{Kind, Section, SectionOrderTracker};
+use crate::Module;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+struct AsyncPositionReader<R> {
+    inner: R,
+    pos: usize,
+    /// Context of the decoding, if it has options.
+    context: Option<DecodeContext>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncPositionReader<R> {
+    fn check_limit(&self, limit: Limit, value: usize) -> Result<(), DecodeError> {
+        match &self.context {
+            Some(context) => context.check_limit(limit, value),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads `size` bytes, growing the buffer in chunks like synchronous
+    /// decoding does, so that a bogus size fails on EOF before allocating
+    /// too much.
+    async fn read_bytes(&mut self, size: usize) -> Result<Vec<u8>, DecodeError> {
+        if let Some(context) = &self.context {
+            context.track_allocation(size)?;
+        }
+        let mut bytes = Vec::new();
+        while bytes.len() < size {
+            let start = bytes.len();
+            bytes.resize(start + (size - start).min(MAX_PREALLOC), 0);
+            self.read_exact(&mut bytes[start..]).await?;
+        }
+        Ok(bytes)
+    }
+
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), DecodeError> {
+        self.inner
+            .read_exact(buf)
+            .await
+            .map_err(DecodeErrorKind::Io)?;
+        self.pos += buf.len();
+        Ok(())
+    }
+
+    /// Reads a single byte, or returns `None` at the end of input.
+    async fn read_byte(&mut self) -> Result<Option<u8>, DecodeError> {
+        let mut byte = 0;
+        if self
+            .inner
+            .read(std::slice::from_mut(&mut byte))
+            .await
+            .map_err(DecodeErrorKind::Io)?
+            == 0
+        {
+            return Ok(None);
+        }
+        self.pos += 1;
+        Ok(Some(byte))
+    }
+
+    async fn read_size(&mut self) -> Result<usize, DecodeError> {
+        let mut buf = [0; MAX_USIZE_LEN];
+        let mut len = 0;
+        loop {
+            self.read_exact(&mut buf[len..=len]).await?;
+            len += 1;
+            if let Some((size, _)) = peek_usize(&buf[..len], false)? {
+                return Ok(size);
+            }
+        }
+    }
+}
+
+async fn decode_sections(
+    r: &mut AsyncPositionReader<impl AsyncRead + Unpin>,
+) -> Result<Module, DecodeError> {
+    let mut magic_and_version = [0; MAGIC_AND_VERSION.len()];
+    let magic_and_version = async {
+        r.read_exact(&mut magic_and_version).await?;
+        MagicAndVersion::decode(&mut &magic_and_version[..])
+    }
+    .await
+    .map_err(|err| err.in_path(PathItem::Name("magic_and_version")))?;
+    let mut sections = Vec::new();
+    let mut section_order_tracker = SectionOrderTracker::default();
+    let mut start = r.pos;
+    while let Some(disc) = r.read_byte().await? {
+        let i = sections.len();
+        let section = async {
+            r.check_limit(Limit::Sections, i + 1)?;
+            let kind =
+                Kind::try_from(disc).map_err(|_| DecodeErrorKind::UnsupportedDiscriminant {
+                    ty: "Section",
+                    discriminant: disc.into(),
+                })?;
+            kind.require_feature(r.context.as_ref())?;
+            section_order_tracker.try_add_kind(kind)?;
+            let size = r.read_size().await?;
+            let offset = r.pos;
+            let raw = r.read_bytes(size).await?;
+            // Captures the context for decoding the contents later.
+            Ok(Section::from_raw(
+                kind,
+                raw,
+                Some(offset),
+                r.context.as_ref(),
+            ))
+        }
+        .await
+        .map_err(move |err: DecodeError| {
+            err.in_path(PathItem::Index(i))
+                .in_path(PathItem::Name("sections"))
+                .at_offset(start)
+        })?;
+        sections.push(section);
+        start = r.pos;
+    }
+    Ok(Module {
+        magic_and_version,
+        sections,
+    })
+}
+
+async fn encode_sections(
+    module: &Module,
+    w: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), EncodeError> {
+    let mut buf = Vec::new();
+    module
+        .magic_and_version
+        .encode(&mut buf)
+        .map_err(|err| err.in_path(PathItem::Name("magic_and_version")))?;
+    let mut section_order_tracker = SectionOrderTracker::default();
+    for (i, section) in module.sections.iter().enumerate() {
+        section_order_tracker
+            .try_add(section)
+            .map_err(EncodeError::from)
+            .and_then(|()| section.encode(&mut buf))
+            .map_err(|err| {
+                err.in_path(PathItem::Index(i))
+                    .in_path(PathItem::Name("sections"))
+            })?;
+        w.write_all(&buf).await?;
+        buf.clear();
+    }
+    w.write_all(&buf).await?;
+    w.flush().await?;
+    Ok(())
+}
+
+async fn decode_async(
+    r: impl AsyncRead + Unpin,
+    context: Option<DecodeContext>,
+) -> Result<Module, DecodeError> {
+    let mut r = AsyncPositionReader {
+        inner: r,
+        pos: 0,
+        context,
+    };
+    decode_sections(&mut r)
+        .await
+        .map_err(|err| err.in_path(PathItem::Variant("Module")).at_offset(r.pos))
+}
+
+impl Module {
+    /// Like [`Module::decode_from`], but reads from an async source.
+    ///
+    /// Only the section framing is read asynchronously; section contents
+    /// are captured as-is and decoded lazily like in the synchronous API.
+    pub async fn decode_from_async(r: impl AsyncRead + Unpin) -> Result<Module, DecodeError> {
+        decode_async(r, None).await
+    }
+
+    /// Like [`Module::decode_with_options`], but reads from an async source.
+    pub async fn decode_with_options_async(
+        r: impl AsyncRead + Unpin,
+        options: &DecodeOptions,
+    ) -> Result<Module, DecodeError> {
+        decode_async(r, Some(DecodeContext::new(options))).await
+    }
+
+    /// Like [`Module::encode_into`], but writes to an async destination.
+    ///
+    /// Each section is encoded into a temporary buffer and then written out.
+    pub async fn encode_into_async<W: AsyncWrite + Unpin>(
+        &self,
+        mut w: W,
+    ) -> Result<W, EncodeError> {
+        encode_sections(self, &mut w)
+            .await
+            .map_err(|err| err.in_path(PathItem::Variant("Module")))?;
+        Ok(w)
+    }
+}
@@ -0,0 +1,482 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::WasmbinCountable;
+use crate::indices::{
+    DataId, ElemId, FieldId, FuncId, GlobalId, LabelId, LocalId, MemId, TableId, TagId, TypeId,
+};
+use crate::io::{
+    Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeOptions, IoError, IoErrorKind,
+    Limit, PathItem, SliceReader,
+};
+use crate::module::MagicAndVersion;
+use crate::sections::{
+    DataInit, ExportDesc, ImportDesc, Kind, NameAssoc, NameMap, Payload, Section as OwnedSection,
+    SectionOrderTracker,
+};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::hash::{Hash, Hasher};
+use custom_debug::Debug as CustomDebug;
+
+/// Decoding of values that borrow from in-memory input.
+///
+/// The context, if any, applies the options of a
+/// [`Module::decode_with_options`] call, same as for owned decoding.
+pub trait DecodeBorrowed<'a>: Sized {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError>;
+}
+
+impl<'a> DecodeBorrowed<'a> for &'a [u8] {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        _context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let size = usize::decode(r)?;
+        if size > r.len() {
+            return Err(IoError::from(IoErrorKind::UnexpectedEof).into());
+        }
+        let (bytes, rest) = r.split_at(size);
+        *r = rest;
+        Ok(bytes)
+    }
+}
+
+impl<'a> DecodeBorrowed<'a> for &'a str {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let bytes = <&[u8]>::decode_borrowed(r, context)?;
+        core::str::from_utf8(bytes).map_err(|_| {
+            // Re-run validation on an owned copy to produce the same error
+            // kind as owned decoding; this only allocates on failure.
+            String::from_utf8(bytes.to_vec()).unwrap_err().into()
+        })
+    }
+}
+
+impl<'a, T: DecodeBorrowed<'a> + WasmbinCountable> DecodeBorrowed<'a> for Vec<T> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let count = usize::decode(r)?;
+        if let Some(context) = context {
+            context.check_limit(Limit::VecLen, count)?;
+            context.track_allocation(count.saturating_mul(core::mem::size_of::<T>()))?;
+        }
+        (0..count)
+            .map(|i| {
+                T::decode_borrowed(r, context).map_err(move |err| err.in_path(PathItem::Index(i)))
+            })
+            .collect()
+    }
+}
+
+impl WasmbinCountable for &[u8] {}
+
+fn decode_field<'a, T: DecodeBorrowed<'a>>(
+    r: &mut &'a [u8],
+    context: Option<&DecodeContext>,
+    name: &'static str,
+) -> Result<T, DecodeError> {
+    T::decode_borrowed(r, context).map_err(|err| err.in_path(PathItem::Name(name)))
+}
+
+fn decode_owned_field<T: Decode>(
+    r: &mut &[u8],
+    context: Option<&DecodeContext>,
+    name: &'static str,
+) -> Result<T, DecodeError> {
+    let mut reader = SliceReader::new(r, None, context);
+    let value = T::decode(&mut reader);
+    let read = r.len() - reader.rest().len();
+    *r = &r[read..];
+    value.map_err(|err| err.in_path(PathItem::Name(name)))
+}
+
+fn decode_all<'a, T: DecodeBorrowed<'a>>(
+    mut r: &'a [u8],
+    context: Option<&DecodeContext>,
+) -> Result<T, DecodeError> {
+    let value = T::decode_borrowed(&mut r, context)?;
+    if !r.is_empty() {
+        return Err(DecodeErrorKind::UnrecognizedData.into());
+    }
+    Ok(value)
+}
+
+/// Context of the decoding that produced a value, so that the same options
+/// apply when decoding its contents.
+///
+/// Always compares equal, like the context of lazily decoded values.
+#[derive(Clone)]
+struct Context(Option<DecodeContext>);
+
+impl PartialEq for Context {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Context {}
+
+impl Hash for Context {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct ImportPath<'a> {
+    pub module: &'a str,
+    pub name: &'a str,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Import<'a> {
+    pub path: ImportPath<'a>,
+    pub desc: ImportDesc,
+}
+
+impl<'a> DecodeBorrowed<'a> for Import<'a> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        Ok(Import {
+            path: ImportPath {
+                module: decode_field(r, context, "module")?,
+                name: decode_field(r, context, "name")?,
+            },
+            desc: decode_owned_field(r, context, "desc")?,
+        })
+    }
+}
+
+impl WasmbinCountable for Import<'_> {}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Export<'a> {
+    pub name: &'a str,
+    pub desc: ExportDesc,
+}
+
+impl<'a> DecodeBorrowed<'a> for Export<'a> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        Ok(Export {
+            name: decode_field(r, context, "name")?,
+            desc: decode_owned_field(r, context, "desc")?,
+        })
+    }
+}
+
+impl WasmbinCountable for Export<'_> {}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Data<'a> {
+    pub init: DataInit,
+    pub blob: &'a [u8],
+}
+
+impl<'a> DecodeBorrowed<'a> for Data<'a> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        Ok(Data {
+            init: decode_owned_field(r, context, "init")?,
+            blob: decode_field(r, context, "blob")?,
+        })
+    }
+}
+
+impl WasmbinCountable for Data<'_> {}
+
+#[derive(CustomDebug, PartialEq, Eq, Hash, Clone)]
+pub struct CustomSection<'a> {
+    pub name: &'a str,
+    pub data: &'a [u8],
+    #[debug(skip)]
+    context: Context,
+}
+
+impl<'a> DecodeBorrowed<'a> for CustomSection<'a> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let name = decode_field(r, context, "name")?;
+        let data = core::mem::take(r);
+        Ok(CustomSection {
+            name,
+            data,
+            context: Context(context.cloned()),
+        })
+    }
+}
+
+impl<'a> CustomSection<'a> {
+    /// Decodes contents of the "name" section, or returns `None` for other
+    /// custom sections.
+    pub fn try_decode_names(&self) -> Option<Result<Vec<NameSubSection<'a>>, DecodeError>> {
+        if self.name != "name" {
+            return None;
+        }
+        Some(decode_all(self.data, self.context.0.as_ref()))
+    }
+}
+
+impl<'a, I: Decode, V: DecodeBorrowed<'a>> DecodeBorrowed<'a> for NameAssoc<I, V> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        Ok(NameAssoc {
+            index: decode_owned_field(r, context, "index")?,
+            value: decode_field(r, context, "value")?,
+        })
+    }
+}
+
+impl<'a, I: Decode, V: DecodeBorrowed<'a>> DecodeBorrowed<'a> for NameMap<I, V> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        Ok(NameMap {
+            items: decode_field(r, context, "items")?,
+        })
+    }
+}
+
+/// Subsection of the "name" section with names borrowed from the input.
+///
+/// Unlike [`NameSubSection`](crate::sections::NameSubSection), it can't be
+/// modified or encoded back; use the owned section for that.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum NameSubSection<'a> {
+    Module(&'a str),
+    Func(NameMap<FuncId, &'a str>),
+    Local(NameMap<FuncId, NameMap<LocalId, &'a str>>),
+    Label(NameMap<FuncId, NameMap<LabelId, &'a str>>),
+    Type(NameMap<TypeId, &'a str>),
+    Table(NameMap<TableId, &'a str>),
+    Memory(NameMap<MemId, &'a str>),
+    Global(NameMap<GlobalId, &'a str>),
+    Elem(NameMap<ElemId, &'a str>),
+    Data(NameMap<DataId, &'a str>),
+    Field(NameMap<TypeId, NameMap<FieldId, &'a str>>),
+    Tag(NameMap<TagId, &'a str>),
+    Unknown { id: u8, data: &'a [u8] },
+}
+
+impl<'a> DecodeBorrowed<'a> for NameSubSection<'a> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let id = u8::decode(r)?;
+        let data = <&[u8]>::decode_borrowed(r, context)?;
+        let (name, res) = match id {
+            0 => ("Module", decode_all(data, context).map(Self::Module)),
+            1 => ("Func", decode_all(data, context).map(Self::Func)),
+            2 => ("Local", decode_all(data, context).map(Self::Local)),
+            3 => ("Label", decode_all(data, context).map(Self::Label)),
+            4 => ("Type", decode_all(data, context).map(Self::Type)),
+            5 => ("Table", decode_all(data, context).map(Self::Table)),
+            6 => ("Memory", decode_all(data, context).map(Self::Memory)),
+            7 => ("Global", decode_all(data, context).map(Self::Global)),
+            8 => ("Elem", decode_all(data, context).map(Self::Elem)),
+            9 => ("Data", decode_all(data, context).map(Self::Data)),
+            10 => ("Field", decode_all(data, context).map(Self::Field)),
+            11 => ("Tag", decode_all(data, context).map(Self::Tag)),
+            _ => return Ok(Self::Unknown { id, data }),
+        };
+        res.map_err(|err| err.in_path(PathItem::Variant(name)))
+    }
+}
+
+impl<'a> DecodeBorrowed<'a> for Vec<NameSubSection<'a>> {
+    fn decode_borrowed(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let mut sub = Vec::new();
+        while !r.is_empty() {
+            let i = sub.len();
+            sub.push(
+                NameSubSection::decode_borrowed(r, context)
+                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
+            );
+        }
+        Ok(sub)
+    }
+}
+
+/// Section payload that can be decoded without copying from the input.
+pub trait BorrowedPayload<'a>: DecodeBorrowed<'a> {
+    const KIND: Kind;
+}
+
+impl<'a> BorrowedPayload<'a> for CustomSection<'a> {
+    const KIND: Kind = Kind::Custom;
+}
+
+impl<'a> BorrowedPayload<'a> for Vec<Import<'a>> {
+    const KIND: Kind = Kind::Import;
+}
+
+impl<'a> BorrowedPayload<'a> for Vec<Export<'a>> {
+    const KIND: Kind = Kind::Export;
+}
+
+/// Function bodies of the Code section, each of which can be decoded into
+/// a [`FuncBody`](crate::sections::FuncBody) separately.
+impl<'a> BorrowedPayload<'a> for Vec<&'a [u8]> {
+    const KIND: Kind = Kind::Code;
+}
+
+impl<'a> BorrowedPayload<'a> for Vec<Data<'a>> {
+    const KIND: Kind = Kind::Data;
+}
+
+#[derive(CustomDebug, PartialEq, Eq, Hash, Clone)]
+pub struct Section<'a> {
+    pub kind: Kind,
+    pub contents: &'a [u8],
+    #[debug(skip)]
+    context: Context,
+}
+
+impl<'a> Section<'a> {
+    pub fn try_decode_borrowed<T: BorrowedPayload<'a>>(&self) -> Option<Result<T, DecodeError>> {
+        if self.kind != T::KIND {
+            return None;
+        }
+        Some(decode_all(self.contents, self.context.0.as_ref()))
+    }
+
+    pub fn try_decode_owned<T: Payload>(&self) -> Option<Result<T, DecodeError>> {
+        if self.kind != T::KIND {
+            return None;
+        }
+        let mut r = SliceReader::new(self.contents, None, self.context.0.as_ref());
+        Some(T::decode(&mut r).and_then(|value| match r.is_empty() {
+            true => Ok(value),
+            false => Err(DecodeErrorKind::UnrecognizedData.into()),
+        }))
+    }
+
+    /// Converts to an owned lazily-decoded section by copying its contents.
+    pub fn to_owned_section(&self) -> OwnedSection {
+        OwnedSection::from_raw(
+            self.kind,
+            self.contents.to_vec(),
+            None,
+            self.context.0.as_ref(),
+        )
+    }
+}
+
+/// Module that borrows section contents, names and data segments from an
+/// in-memory input instead of copying them.
+///
+/// Only payloads implementing [`BorrowedPayload`], as well as the "name"
+/// section via [`CustomSection::try_decode_names`], are decoded without
+/// copying. Other sections can be decoded with
+/// [`Section::try_decode_owned`], which copies any strings they contain.
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct Module<'a> {
+    pub sections: Vec<Section<'a>>,
+}
+
+impl<'a> Module<'a> {
+    pub fn decode_from(input: &'a [u8]) -> Result<Self, DecodeError> {
+        Self::decode_with_context(input, None)
+    }
+
+    /// Like [`Module::decode_from`], but applies the given options.
+    ///
+    /// Options also apply when decoding the contents of the sections later.
+    pub fn decode_with_options(
+        input: &'a [u8],
+        options: &DecodeOptions,
+    ) -> Result<Self, DecodeError> {
+        Self::decode_with_context(input, Some(&DecodeContext::new(options)))
+    }
+
+    fn decode_with_context(
+        input: &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        let mut r = input;
+        Self::decode_sections(&mut r, context).map_err(|err| err.at_offset(input.len() - r.len()))
+    }
+
+    fn decode_sections(
+        r: &mut &'a [u8],
+        context: Option<&DecodeContext>,
+    ) -> Result<Self, DecodeError> {
+        MagicAndVersion::decode(r)
+            .map_err(|err| err.in_path(PathItem::Name("magic_and_version")))?;
+        let mut sections = Vec::new();
+        let mut section_order_tracker = SectionOrderTracker::default();
+        while let Some((&disc, rest)) = r.split_first() {
+            *r = rest;
+            let i = sections.len();
+            (|| -> Result<(), DecodeError> {
+                let kind =
+                    Kind::try_from(disc).map_err(|_| DecodeErrorKind::UnsupportedDiscriminant {
+                        ty: "Section",
+                        discriminant: disc.into(),
+                    })?;
+                if let Some(context) = context {
+                    context.check_limit(Limit::Sections, i + 1)?;
+                }
+                kind.require_feature(context)?;
+                section_order_tracker.try_add_kind(kind)?;
+                sections.push(Section {
+                    kind,
+                    contents: DecodeBorrowed::decode_borrowed(r, context)?,
+                    context: Context(context.cloned()),
+                });
+                Ok(())
+            })()
+            .map_err(move |err| {
+                err.in_path(PathItem::Index(i))
+                    .in_path(PathItem::Name("sections"))
+            })?;
+        }
+        Ok(Module { sections })
+    }
+
+    /// Converts to an owned module by copying section contents.
+    pub fn to_owned_module(&self) -> crate::Module {
+        crate::Module {
+            magic_and_version: MagicAndVersion,
+            sections: self
+                .sections
+                .iter()
+                .map(Section::to_owned_section)
+                .collect(),
+        }
+    }
+}
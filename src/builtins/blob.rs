@@ -12,43 +12,151 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::integers::{decode_usize_with_width, encode_usize_with_width};
 use crate::builtins::{Lazy, WasmbinCountable};
-use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode};
+use crate::io::{
+    preserve_encoding, remaining_allocation, track_allocation, Decode, DecodeContext, DecodeError,
+    DecodeErrorKind, Encode, EncodeError,
+};
+use crate::io::{IoError, IoErrorKind, Read};
 use crate::visit::Visit;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
+use core::convert::TryFrom;
 
-#[derive(Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct RawBlob<T = Vec<u8>> {
     pub contents: T,
 }
 
 impl<T: AsRef<[u8]>> Encode for RawBlob<T> {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         let bytes = self.contents.as_ref();
         bytes.len().encode(w)?;
         bytes.encode(w)
     }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        let len = self.contents.as_ref().len();
+        Ok(len.encoded_len()? + len)
+    }
 }
 
 impl<T: Decode> Decode for RawBlob<T> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         let size = u32::decode(r)?;
-        let mut taken = std::io::Read::take(r, size.into());
+        let mut taken = Take {
+            inner: r,
+            limit: usize::try_from(size)?,
+        };
         let contents = T::decode(&mut taken)?;
-        if taken.limit() != 0 {
+        if taken.limit != 0 {
             return Err(DecodeErrorKind::UnrecognizedData.into());
         }
         Ok(RawBlob { contents })
     }
 }
 
+/// Reader limited to a number of bytes.
+///
+/// Holds `&mut R` instead of `R` as `&mut R` isn't guaranteed to implement
+/// [`Read`] without `std`.
+struct Take<'a, R: ?Sized> {
+    inner: &'a mut R,
+    limit: usize,
+}
+
+impl<R: Read> Read for Take<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let max = buf.len().min(self.limit);
+        let n = self.inner.read(&mut buf[..max])?;
+        self.limit -= n;
+        Ok(n)
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        if self.limit == 0 {
+            return Err(IoErrorKind::UnexpectedEof.into());
+        }
+        let byte = self.inner.read_byte()?;
+        self.limit -= 1;
+        Ok(byte)
+    }
+
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        if self.limit == 0 {
+            return Ok(None);
+        }
+        let byte = self.inner.try_read_byte()?;
+        self.limit -= usize::from(byte.is_some());
+        Ok(byte)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.inner.position()
+    }
+
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        self.inner.decode_context()
+    }
+}
+
+/// Reads a size-prefixed byte sequence.
+///
+/// Unlike `RawBlob<Vec<u8>>`, this allocates the buffer upfront instead of
+/// growing it while reading, but caps the allocation in case the size is
+/// bogus.
+pub(crate) fn decode_sized_bytes(r: &mut impl crate::io::Read) -> Result<Vec<u8>, DecodeError> {
+    let size = usize::decode(r)?;
+    read_bytes(r, size)
+}
+
+/// Maximum number of bytes to allocate upfront for input of a claimed size.
+pub(crate) const MAX_PREALLOC: usize = 1 << 16;
+
+fn read_bytes(r: &mut impl crate::io::Read, size: usize) -> Result<Vec<u8>, DecodeError> {
+    track_allocation(r, size)?;
+    let mut bytes = Vec::new();
+    // Grow the buffer in chunks, so that a bogus size fails on EOF before
+    // allocating too much.
+    while bytes.len() < size {
+        let start = bytes.len();
+        bytes.resize(start + (size - start).min(MAX_PREALLOC), 0);
+        r.read_exact(&mut bytes[start..])?;
+    }
+    Ok(bytes)
+}
+
+/// Reads all the remaining bytes.
+///
+/// Stops reading as soon as the allocation limit is exceeded instead of
+/// reading the whole input first.
+pub(crate) fn read_to_end(r: &mut impl crate::io::Read) -> Result<Vec<u8>, DecodeError> {
+    let mut bytes = Vec::new();
+    match remaining_allocation(r) {
+        usize::MAX => r.read_to_end(&mut bytes)?,
+        remaining => Take {
+            inner: r,
+            limit: remaining.saturating_add(1),
+        }
+        .read_to_end(&mut bytes)?,
+    };
+    track_allocation(r, bytes.len())?;
+    Ok(bytes)
+}
+
 impl<T: AsRef<[u8]>> AsRef<[u8]> for RawBlob<T> {
     fn as_ref(&self) -> &[u8] {
         self.contents.as_ref()
     }
 }
 
-#[derive(Default, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Blob<T: Decode> {
     contents: Lazy<T>,
 }
@@ -65,10 +173,32 @@ impl<T: Decode> Blob<T> {
     pub fn try_into_contents(self) -> Result<T, DecodeError> {
         self.contents.try_into_contents()
     }
+
+    pub fn try_as_raw(&self) -> Result<&[u8], &T> {
+        self.contents.try_as_raw()
+    }
+
+    /// Wraps contents read from the input at `offset` by a decoding with
+    /// the given context.
+    pub(crate) fn from_input(
+        raw: Vec<u8>,
+        offset: Option<usize>,
+        context: Option<&DecodeContext>,
+    ) -> Self {
+        Blob {
+            contents: Lazy::from_input(raw, offset, context, None),
+        }
+    }
+
+    pub(crate) fn from_raw_parsed(raw: Vec<u8>, value: T) -> Self {
+        Blob {
+            contents: Lazy::from_raw_parsed(raw, value),
+        }
+    }
 }
 
-impl<T: Decode + std::fmt::Debug> std::fmt::Debug for Blob<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl<T: Decode + core::fmt::Debug> core::fmt::Debug for Blob<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         f.write_str("Blob(")?;
         self.contents.fmt(f)?;
         f.write_str(")")
@@ -76,22 +206,54 @@ impl<T: Decode + std::fmt::Debug> std::fmt::Debug for Blob<T> {
 }
 
 impl<T: Decode + Encode> Encode for Blob<T> {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        let value = match self.contents.try_as_raw() {
-            Ok(raw) => return RawBlob { contents: raw }.encode(w),
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        let value = match self.contents.try_as_original() {
+            Ok(raw) => {
+                return match self.contents.original_size_width() {
+                    Some(width) => {
+                        encode_usize_with_width(raw.len(), width, w)?;
+                        raw.encode(w)
+                    }
+                    None => RawBlob { contents: raw }.encode(w),
+                }
+            }
             Err(value) => value,
         };
-        let mut buf;
-        buf = Vec::new();
-        value.encode(&mut buf)?;
-        RawBlob { contents: buf }.encode(w)
+        // Write the size prefix upfront so that the value can be encoded
+        // straight into the output instead of an intermediate buffer.
+        value.encoded_len()?.encode(w)?;
+        value.encode(w)
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        let len = match self.contents.try_as_original() {
+            Ok(raw) => {
+                let len = raw.len();
+                let size_len = len.encoded_len()?;
+                return Ok(match self.contents.original_size_width() {
+                    Some(width) => size_len.max(width.into()),
+                    None => size_len,
+                } + len);
+            }
+            Err(value) => value.encoded_len()?,
+        };
+        Ok(len.encoded_len()? + len)
     }
 }
 
 impl<T: Decode> Decode for Blob<T> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let contents: Lazy<T> = RawBlob::decode(r)?.contents;
-        Ok(Self { contents })
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let (size, width) = if preserve_encoding(r) {
+            let (size, width) = decode_usize_with_width(r)?;
+            (size, Some(width))
+        } else {
+            (usize::decode(r)?, None)
+        };
+        let offset = r.position();
+        let raw = read_bytes(r, size)?;
+        Ok(Blob {
+            contents: Lazy::from_input(raw, offset, r.decode_context(), width),
+        })
     }
 }
 
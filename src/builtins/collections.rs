@@ -12,35 +12,58 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::io::{Decode, DecodeError, Encode, PathItem};
+use crate::io::{
+    check_limit, track_allocation, Decode, DecodeError, Encode, EncodeError, Limit, PathItem,
+};
+use alloc::vec::Vec;
 
 pub use wasmbin_derive::WasmbinCountable;
 pub trait WasmbinCountable {}
 
 impl<T: WasmbinCountable + Encode> Encode for [T] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         self.len().encode(w)?;
-        for item in self {
-            item.encode(w)?;
+        for (i, item) in self.iter().enumerate() {
+            item.encode(w)
+                .map_err(move |err| err.in_path(PathItem::Index(i)))?;
         }
         Ok(())
     }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        let mut len = self.len().encoded_len()?;
+        for (i, item) in self.iter().enumerate() {
+            len += item
+                .encoded_len()
+                .map_err(move |err| err.in_path(PathItem::Index(i)))?;
+        }
+        Ok(len)
+    }
 }
 
 impl<T> Encode for Vec<T>
 where
     [T]: Encode,
 {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         self.as_slice().encode(w)
     }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        self.as_slice().encoded_len()
+    }
 }
 
 impl<T: WasmbinCountable + Decode> Decode for Vec<T> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         let count = usize::decode(r)?;
+        check_limit(r, Limit::VecLen, count)?;
+        track_allocation(r, count.saturating_mul(core::mem::size_of::<T>()))?;
         (0..count)
-            .map(|i| T::decode(r).map_err(move |err| err.in_path(PathItem::Index(i))))
+            .map(|i| {
+                let start = r.position();
+                T::decode(r).map_err(move |err| err.in_path(PathItem::Index(i)).at_position(start))
+            })
             .collect()
     }
 }
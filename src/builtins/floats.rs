@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::io::{Decode, DecodeError, Encode, Wasmbin};
+use crate::io::{Decode, DecodeError, Encode, EncodeError, Wasmbin};
 use crate::visit::Visit;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 /// A wrapper around floats that treats `NaN`s as equal.
 ///
 /// This is useful in instruction context, where we don't care
 /// about general floating number rules.
-#[derive(Wasmbin, Debug, Arbitrary, Clone, Visit)]
+#[derive(Wasmbin, Debug, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct FloatConst<F> {
     pub value: F,
 }
@@ -30,13 +32,13 @@ impl<F> Eq for FloatConst<F> where Self: PartialEq {}
 macro_rules! def_float {
     ($ty:ident) => {
         impl Encode for $ty {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
                 self.to_le_bytes().encode(w)
             }
         }
 
         impl Decode for $ty {
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
                 Decode::decode(r).map($ty::from_le_bytes)
             }
         }
@@ -49,8 +51,8 @@ macro_rules! def_float {
             }
         }
 
-        impl std::hash::Hash for FloatConst<$ty> {
-            fn hash<H: std::hash::Hasher>(&self, h: &mut H) {
+        impl core::hash::Hash for FloatConst<$ty> {
+            fn hash<H: core::hash::Hasher>(&self, h: &mut H) {
                 h.write(&self.value.to_ne_bytes())
             }
         }
@@ -12,20 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode};
+use crate::io::{Decode, DecodeError, Encode, EncodeError, Leb128Error};
 use crate::visit::Visit;
-use std::convert::TryFrom;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 
 macro_rules! def_byte_array {
     ($count:literal) => {
         impl Encode for [u8; $count] {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-                w.write_all(self)
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+                Ok(w.write_all(self)?)
+            }
+
+            fn encoded_len(&self) -> Result<usize, EncodeError> {
+                Ok($count)
             }
         }
 
         impl Decode for [u8; $count] {
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
                 let mut dest = [0_u8; $count];
                 r.read_exact(&mut dest)?;
                 Ok(dest)
@@ -39,63 +44,146 @@ def_byte_array!(8);
 def_byte_array!(16);
 
 impl Encode for u8 {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        std::slice::from_ref(self).encode(w)
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        core::slice::from_ref(self).encode(w)
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        Ok(1)
     }
 }
 
 impl Encode for [u8] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        w.write_all(self)
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        Ok(w.write_all(self)?)
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        Ok(self.len())
     }
 }
 
 impl Decode for u8 {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let mut dest = 0;
-        r.read_exact(std::slice::from_mut(&mut dest))?;
-        Ok(dest)
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        Ok(r.read_byte()?)
     }
 }
 
 impl Decode for Option<u8> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let mut dest = 0;
-        loop {
-            return match r.read(std::slice::from_mut(&mut dest)) {
-                Ok(0) => Ok(None),
-                Ok(_) => Ok(Some(dest)),
-                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
-                Err(err) => Err(DecodeErrorKind::Io(err).into()),
-            };
-        }
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        Ok(r.try_read_byte()?)
     }
 }
 
 impl Decode for Vec<u8> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let mut dest = Vec::new();
-        r.read_to_end(&mut dest)?;
-        Ok(dest)
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        super::blob::read_to_end(r)
     }
 }
 
 impl Visit for u8 {}
 
+/// Maximum number of bytes in a LEB128 encoding of an integer of the given size.
+const fn leb128_limit(bits: u32) -> u32 {
+    bits / 7 + 1
+}
+
+// These are hot paths, so instead of wrapping the reader into an adapter,
+// read bytes one by one via `Read::read_byte`, which in-memory readers
+// implement as a simple bounds check. Errors are kept small until the very
+// end, as moving `DecodeError` around on each byte is noticeably slower.
+
+fn decode_unsigned(r: &mut impl crate::io::Read, limit: u32) -> Result<u64, Leb128Error> {
+    let mut result = 0_u64;
+    let mut shift = 0;
+    for _ in 0..limit {
+        let byte = r.read_byte()?;
+        let low_bits = u64::from(byte & 0x7F);
+        if shift == 63 && low_bits > 1 {
+            break;
+        }
+        result |= low_bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+    Err(Leb128Error::Overflow)
+}
+
+fn decode_signed(r: &mut impl crate::io::Read, limit: u32) -> Result<i64, Leb128Error> {
+    let mut result = 0_i64;
+    let mut shift = 0;
+    for _ in 0..limit {
+        let byte = r.read_byte()?;
+        let low_bits = i64::from(byte & 0x7F);
+        if shift == 63 && low_bits != 0 && low_bits != 0x7F {
+            break;
+        }
+        result |= low_bits << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                // Sign-extend.
+                result |= !0 << shift;
+            }
+            return Ok(result);
+        }
+    }
+    Err(Leb128Error::Overflow)
+}
+
+// Encoded into a stack buffer first so that the writer is called only once.
+
+#[allow(clippy::cast_possible_truncation)]
+fn encode_unsigned(mut value: u64, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+    let mut buf = [0_u8; leb128_limit(u64::BITS) as usize];
+    let mut len = 0;
+    loop {
+        let byte = value as u8 & 0x7F;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    Ok(w.write_all(&buf[..len])?)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn encode_signed(mut value: i64, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+    let mut buf = [0_u8; leb128_limit(i64::BITS) as usize];
+    let mut len = 0;
+    loop {
+        let byte = value as u8 & 0x7F;
+        // Arithmetic shift, so the sign is preserved.
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    Ok(w.write_all(&buf[..len])?)
+}
+
 macro_rules! def_integer {
-    ($ty:ident, $leb128_method:ident) => {
+    ($ty:ident, $encode:ident, $decode:ident) => {
         impl Encode for $ty {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-                leb128::write::$leb128_method(w, (*self).into()).map(|_| ())
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+                $encode((*self).into(), w)
             }
         }
 
         impl Decode for $ty {
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-                const LIMIT: u64 = (std::mem::size_of::<$ty>() * 8 / 7) as u64 + 1;
-
-                let mut r = std::io::Read::take(r, LIMIT);
-                let as_64 = leb128::read::$leb128_method(&mut r)?;
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+                let as_64 = $decode(r, leb128_limit(<$ty>::BITS))?;
                 let res = Self::try_from(as_64)?;
 
                 Ok(res)
@@ -106,24 +194,144 @@ macro_rules! def_integer {
     };
 }
 
-def_integer!(u32, unsigned);
-def_integer!(i32, signed);
-def_integer!(u64, unsigned);
-def_integer!(i64, signed);
+def_integer!(u32, encode_unsigned, decode_unsigned);
+def_integer!(i32, encode_signed, decode_signed);
+def_integer!(u64, encode_unsigned, decode_unsigned);
+def_integer!(i64, encode_signed, decode_signed);
 
 impl Encode for usize {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        match u32::try_from(*self) {
-            Ok(v) => v.encode(w),
-            Err(err) => Err(std::io::Error::new(std::io::ErrorKind::InvalidData, err)),
-        }
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        u32::try_from(*self)?.encode(w)
     }
 }
 
 impl Decode for usize {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         Ok(usize::try_from(u32::decode(r)?)?)
     }
 }
 
 impl Visit for usize {}
+
+/// Maximum length of a LEB128-encoded `usize`.
+pub(crate) const MAX_USIZE_LEN: usize = leb128_limit(u32::BITS) as usize;
+
+/// Decodes a `usize` from the start of buffered input, returning it along
+/// with its length, or `None` if more bytes are needed.
+///
+/// `complete` indicates that no more bytes can follow.
+pub(crate) fn peek_usize(
+    bytes: &[u8],
+    complete: bool,
+) -> Result<Option<(usize, usize)>, DecodeError> {
+    let len = match bytes.iter().position(|b| b & 0x80 == 0) {
+        Some(pos) => pos + 1,
+        None if !complete && bytes.len() < MAX_USIZE_LEN => return Ok(None),
+        None => bytes.len().min(MAX_USIZE_LEN),
+    };
+    let value = usize::decode(&mut &bytes[..len])?;
+    Ok(Some((value, len)))
+}
+
+/// Decodes a `usize` along with the number of bytes it took in the input.
+pub(crate) fn decode_usize_with_width(
+    r: &mut impl crate::io::Read,
+) -> Result<(usize, u8), DecodeError> {
+    let mut buf = [0_u8; MAX_USIZE_LEN];
+    let mut width = 0;
+    for dest in &mut buf {
+        *dest = r.read_byte()?;
+        width += 1;
+        if *dest & 0x80 == 0 {
+            break;
+        }
+    }
+    let value = usize::decode(&mut &buf[..usize::from(width)])?;
+    Ok((value, width))
+}
+
+/// Encodes a `usize` padded to the given width, or in the canonical form if
+/// it doesn't fit.
+pub(crate) fn encode_usize_with_width(
+    value: usize,
+    width: u8,
+    w: &mut impl crate::io::Write,
+) -> Result<(), EncodeError> {
+    let mut value = u32::try_from(value)?;
+    if width == 0 || u64::from(value) >> (7 * u32::from(width - 1)) >= 0x80 {
+        return value.encode(w);
+    }
+    for _ in 1..width {
+        #[allow(clippy::cast_possible_truncation)]
+        let low_bits = value as u8 & 0x7F;
+        w.write_all(&[low_bits | 0x80])?;
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let last = value as u8;
+    Ok(w.write_all(&[last])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn peek_usize_waits_for_more_bytes() {
+        assert_eq!(peek_usize(&[], false).unwrap(), None);
+        assert_eq!(peek_usize(&[0x80], false).unwrap(), None);
+        assert_eq!(peek_usize(&[0x80; 4], false).unwrap(), None);
+    }
+
+    #[test]
+    fn peek_usize_returns_value_and_length() {
+        assert_eq!(peek_usize(&[0x05], false).unwrap(), Some((5, 1)));
+        // Trailing bytes are left alone.
+        assert_eq!(
+            peek_usize(&[0x80, 0x01, 0xFF], false).unwrap(),
+            Some((128, 2))
+        );
+        // Non-canonical encodings report their full length.
+        assert_eq!(peek_usize(&[0x85, 0x80, 0x00], true).unwrap(), Some((5, 3)));
+    }
+
+    #[test]
+    fn peek_usize_rejects_incomplete_or_overlong_input() {
+        assert!(peek_usize(&[], true).is_err());
+        assert!(peek_usize(&[0x80], true).is_err());
+        assert!(peek_usize(&[0x80; MAX_USIZE_LEN], false).is_err());
+    }
+
+    fn encode_with_width(value: usize, width: u8) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        encode_usize_with_width(value, width, &mut encoded).unwrap();
+        encoded
+    }
+
+    #[test]
+    fn usize_with_width_round_trips() {
+        for value in [0, 5, 127, 128, 300, 1 << 21, u32::MAX as usize] {
+            for width in 1..=5 {
+                let encoded = encode_with_width(value, width);
+                let (decoded, decoded_width) =
+                    decode_usize_with_width(&mut encoded.as_slice()).unwrap();
+                assert_eq!(decoded, value);
+                assert_eq!(usize::from(decoded_width), encoded.len());
+            }
+        }
+    }
+
+    #[test]
+    fn usize_with_width_pads_or_falls_back_to_canonical() {
+        assert_eq!(encode_with_width(5, 3), [0x85, 0x80, 0x00]);
+        assert_eq!(
+            decode_usize_with_width(&mut &[0x85, 0x80, 0x00, 0xFF][..]).unwrap(),
+            (5, 3)
+        );
+        // Values that don't fit in the width are encoded canonically, as is
+        // the missing width.
+        assert_eq!(encode_with_width(200, 1), [0xC8, 0x01]);
+        assert_eq!(encode_with_width(200, 0), [0xC8, 0x01]);
+    }
+}
@@ -13,12 +13,20 @@
 // limitations under the License.
 
 use crate::builtins::WasmbinCountable;
-use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode};
+use crate::io::{
+    Decode, DecodeContext, DecodeError, DecodeErrorKind, Encode, EncodeError, Read, SliceReader,
+};
 use crate::visit::{Visit, VisitError};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
+use core::hash::Hash;
 use custom_debug::Debug as CustomDebug;
+#[cfg(feature = "std")]
 use once_cell::sync::OnceCell;
-use std::hash::Hash;
+#[cfg(not(feature = "std"))]
+use once_cell::unsync::OnceCell;
 
 #[derive(CustomDebug, Clone)]
 enum LazyStatus<T> {
@@ -26,10 +34,39 @@ enum LazyStatus<T> {
         #[debug(with = "custom_debug::hexbuf_str")]
         raw: Vec<u8>,
         parsed: OnceCell<T>,
+        /// Offset of the input in the whole input, if known.
+        offset: Option<usize>,
     },
     Output {
         value: T,
     },
+    // Boxed to avoid growing all the other lazy values.
+    FromInputWithContext(Box<InputWithContext<T>>),
+    /// Contents accessed mutably after decoding with
+    /// [`DecodeOptions::preserve_encoding`](crate::io::DecodeOptions::preserve_encoding).
+    ///
+    /// The input is kept so that it can still be written back if the
+    /// contents turn out to be unchanged. Its `parsed` cell caches the
+    /// original contents to compare against.
+    OutputWithInput {
+        value: T,
+        input: Box<InputWithContext<T>>,
+    },
+}
+
+/// Input decoded with [`Module::decode_with_options`](crate::Module::decode_with_options).
+#[derive(CustomDebug, Clone)]
+struct InputWithContext<T> {
+    #[debug(with = "custom_debug::hexbuf_str")]
+    raw: Vec<u8>,
+    parsed: OnceCell<T>,
+    offset: Option<usize>,
+    /// Context of the decoding that produced the input, applied again when
+    /// decoding the contents.
+    #[debug(skip)]
+    context: DecodeContext,
+    /// Width of the size prefix that preceded the input, if preserved.
+    size_width: Option<u8>,
 }
 
 #[derive(Clone)]
@@ -38,11 +75,51 @@ pub struct Lazy<T> {
 }
 
 impl<T> Lazy<T> {
+    /// Wraps input of a decoding with the given context, so that its
+    /// options apply to the contents too, and errors in the contents are
+    /// reported at their offsets in the whole input.
+    pub(crate) fn from_input(
+        raw: Vec<u8>,
+        offset: Option<usize>,
+        context: Option<&DecodeContext>,
+        size_width: Option<u8>,
+    ) -> Self {
+        if let Some(context) = context {
+            return Lazy {
+                status: LazyStatus::FromInputWithContext(Box::new(InputWithContext {
+                    raw,
+                    parsed: OnceCell::new(),
+                    offset,
+                    size_width: size_width.filter(|_| context.options().preserve_encoding),
+                    context: context.clone(),
+                })),
+            };
+        }
+        Lazy {
+            status: LazyStatus::FromInput {
+                raw,
+                parsed: OnceCell::new(),
+                offset,
+            },
+        }
+    }
+
     pub fn from_raw(raw: Vec<u8>) -> Self {
         Lazy {
             status: LazyStatus::FromInput {
                 raw,
                 parsed: OnceCell::new(),
+                offset: None,
+            },
+        }
+    }
+
+    pub(crate) fn from_raw_parsed(raw: Vec<u8>, value: T) -> Self {
+        Lazy {
+            status: LazyStatus::FromInput {
+                raw,
+                parsed: OnceCell::from(value),
+                offset: None,
             },
         }
     }
@@ -50,7 +127,45 @@ impl<T> Lazy<T> {
     pub fn try_as_raw(&self) -> Result<&[u8], &T> {
         match &self.status {
             LazyStatus::FromInput { raw, .. } => Ok(raw),
-            LazyStatus::Output { value } => Err(value),
+            LazyStatus::Output { value } | LazyStatus::OutputWithInput { value, .. } => Err(value),
+            LazyStatus::FromInputWithContext(input) => Ok(&input.raw),
+        }
+    }
+
+    /// Width of the size prefix in the input, if it was preserved.
+    pub(crate) fn original_size_width(&self) -> Option<u8> {
+        match &self.status {
+            LazyStatus::FromInputWithContext(input) | LazyStatus::OutputWithInput { input, .. } => {
+                input.size_width
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<T: Decode + Encode> Lazy<T> {
+    /// Like [`Lazy::try_as_raw`], but also returns the preserved input of
+    /// mutably accessed contents if they're still the same.
+    pub(crate) fn try_as_original(&self) -> Result<&[u8], &T> {
+        let LazyStatus::OutputWithInput { value, input } = &self.status else {
+            return self.try_as_raw();
+        };
+        // Compare encodings rather than values, as `PartialEq` can be too
+        // lenient, e.g. `FloatConst` treats all NaNs as equal. Both sides
+        // write nested contents that are unchanged as they were read, so
+        // they only differ if something was actually modified.
+        let encode = |value: &T| {
+            let mut buf = Vec::new();
+            value.encode(&mut buf).ok().map(|()| buf)
+        };
+        let unchanged = input
+            .parsed
+            .get_or_try_init(|| decode_raw(Some(&input.context), &input.raw, input.offset))
+            .is_ok_and(|original| encode(value).is_some_and(|buf| Some(buf) == encode(original)));
+        if unchanged {
+            Ok(&input.raw)
+        } else {
+            Err(value)
         }
     }
 }
@@ -69,68 +184,129 @@ impl<T: Default> Default for Lazy<T> {
     }
 }
 
-impl<T: Encode> Encode for Lazy<T> {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        match &self.status {
-            LazyStatus::FromInput { raw, .. } => raw.encode(w),
-            LazyStatus::Output { value } => value.encode(w),
+impl<T: Decode + Encode> Encode for Lazy<T> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        match self.try_as_original() {
+            Ok(raw) => raw.encode(w),
+            Err(value) => value.encode(w),
+        }
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        match self.try_as_original() {
+            Ok(raw) => Ok(raw.len()),
+            Err(value) => value.encoded_len(),
         }
     }
 }
 
 impl<T: Decode> Decode for Lazy<T> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        Vec::decode(r).map(Self::from_raw)
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let offset = r.position();
+        Vec::decode(r).map(|raw| Self::from_input(raw, offset, r.decode_context(), None))
     }
 }
 
-impl<T: std::fmt::Debug> std::fmt::Debug for Lazy<T> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<T: core::fmt::Debug> core::fmt::Debug for Lazy<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         self.status.fmt(f)
     }
 }
 
-fn decode_raw<T: Decode>(mut raw: &[u8]) -> Result<T, DecodeError> {
-    let value = T::decode(&mut raw)?;
-    if !raw.is_empty() {
-        return Err(DecodeErrorKind::UnrecognizedData.into());
-    }
-    Ok(value)
+/// Decodes `raw` with the context it was read with, so that contents are
+/// decoded the same way no matter where they're accessed.
+fn decode_raw<T: Decode>(
+    context: Option<&DecodeContext>,
+    raw: &[u8],
+    offset: Option<usize>,
+) -> Result<T, DecodeError> {
+    let mut r = SliceReader::new(raw, offset, context);
+    (|| -> Result<T, DecodeError> {
+        let value = T::decode(&mut r)?;
+        if !r.is_empty() {
+            return Err(DecodeErrorKind::UnrecognizedData.into());
+        }
+        Ok(value)
+    })()
+    .map_err(|err| err.at_position(r.position()))
 }
 
 impl<T: Decode> Lazy<T> {
     pub fn try_contents(&self) -> Result<&T, DecodeError> {
         match &self.status {
-            LazyStatus::FromInput { raw, parsed } => parsed.get_or_try_init(|| decode_raw(raw)),
-            LazyStatus::Output { value } => Ok(value),
+            LazyStatus::FromInput {
+                raw,
+                parsed,
+                offset,
+            } => parsed.get_or_try_init(|| decode_raw(None, raw, *offset)),
+            LazyStatus::Output { value } | LazyStatus::OutputWithInput { value, .. } => Ok(value),
+            LazyStatus::FromInputWithContext(input) => input
+                .parsed
+                .get_or_try_init(|| decode_raw(Some(&input.context), &input.raw, input.offset)),
         }
     }
 
     pub fn try_contents_mut(&mut self) -> Result<&mut T, DecodeError> {
-        if let LazyStatus::FromInput { raw, parsed } = &mut self.status {
-            // We can't trust input and output to match once we obtained a mutable reference,
-            // so get the value and change the status to just Output.
-            let parsed = std::mem::replace(parsed, OnceCell::new());
-            self.status = LazyStatus::Output {
-                value: match parsed.into_inner() {
+        match &mut self.status {
+            LazyStatus::FromInput {
+                raw,
+                parsed,
+                offset,
+            } => {
+                // We can't trust input and output to match once we obtained a mutable reference,
+                // so get the value and change the status to just Output.
+                let parsed = core::mem::replace(parsed, OnceCell::new());
+                self.status = LazyStatus::Output {
+                    value: match parsed.into_inner() {
+                        Some(value) => value,
+                        None => decode_raw(None, raw, *offset)?,
+                    },
+                };
+            }
+            LazyStatus::FromInputWithContext(input) => {
+                let value = match input.parsed.take() {
                     Some(value) => value,
-                    None => decode_raw(raw)?,
-                },
-            };
+                    None => decode_raw(Some(&input.context), &input.raw, input.offset)?,
+                };
+                let placeholder = LazyStatus::FromInput {
+                    raw: Vec::new(),
+                    parsed: OnceCell::new(),
+                    offset: None,
+                };
+                self.status = match core::mem::replace(&mut self.status, placeholder) {
+                    // Same as above, but keep the input, so that it's still
+                    // written back if the contents turn out to be unchanged.
+                    LazyStatus::FromInputWithContext(input)
+                        if input.context.options().preserve_encoding =>
+                    {
+                        LazyStatus::OutputWithInput { value, input }
+                    }
+                    _ => LazyStatus::Output { value },
+                };
+            }
+            LazyStatus::Output { .. } | LazyStatus::OutputWithInput { .. } => {}
         }
-        if let LazyStatus::Output { value } = &mut self.status {
-            return Ok(value);
+        match &mut self.status {
+            LazyStatus::Output { value } | LazyStatus::OutputWithInput { value, .. } => Ok(value),
+            _ => unsafe { core::hint::unreachable_unchecked() },
         }
-        unsafe { std::hint::unreachable_unchecked() }
     }
 
     pub fn try_into_contents(self) -> Result<T, DecodeError> {
         match self.status {
-            LazyStatus::FromInput { raw, parsed } => match parsed.into_inner() {
+            LazyStatus::FromInput {
+                raw,
+                parsed,
+                offset,
+            } => match parsed.into_inner() {
+                Some(value) => Ok(value),
+                None => decode_raw(None, &raw, offset),
+            },
+            LazyStatus::Output { value } | LazyStatus::OutputWithInput { value, .. } => Ok(value),
+            LazyStatus::FromInputWithContext(input) => match input.parsed.into_inner() {
                 Some(value) => Ok(value),
-                None => decode_raw(&raw),
+                None => decode_raw(Some(&input.context), &input.raw, input.offset),
             },
-            LazyStatus::Output { value } => Ok(value),
         }
     }
 }
@@ -154,11 +330,12 @@ impl<T: Decode + PartialEq> PartialEq for Lazy<T> {
 impl<T: Decode + Eq> Eq for Lazy<T> {}
 
 impl<T: Decode + Hash> Hash for Lazy<T> {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
         self.try_contents().ok().hash(state);
     }
 }
 
+#[cfg(feature = "arbitrary")]
 impl<'a, T: Arbitrary<'a>> Arbitrary<'a> for Lazy<T> {
     fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
         T::arbitrary(u).map(Self::from)
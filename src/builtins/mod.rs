@@ -23,4 +23,7 @@ mod strings;
 pub use blob::{Blob, RawBlob};
 pub use collections::WasmbinCountable;
 pub use floats::FloatConst;
+pub(crate) use integers::peek_usize;
 pub use lazy::Lazy;
+#[cfg(feature = "async")]
+pub(crate) use {blob::MAX_PREALLOC, integers::MAX_USIZE_LEN};
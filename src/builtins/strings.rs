@@ -12,26 +12,38 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::RawBlob;
-use crate::io::{Decode, DecodeError, Encode};
+use super::blob::decode_sized_bytes;
+use super::{RawBlob, WasmbinCountable};
+use crate::io::{Decode, DecodeError, Encode, EncodeError};
 use crate::visit::Visit;
+use alloc::string::String;
 
 impl Encode for str {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         RawBlob { contents: self }.encode(w)
     }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        RawBlob { contents: self }.encoded_len()
+    }
 }
 
 impl Encode for String {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         self.as_str().encode(w)
     }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        self.as_str().encoded_len()
+    }
 }
 
 impl Decode for String {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        Ok(String::from_utf8(RawBlob::decode(r)?.contents)?)
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        Ok(String::from_utf8(decode_sized_bytes(r)?)?)
     }
 }
 
+impl WasmbinCountable for String {}
+
 impl Visit for String {}
@@ -0,0 +1,201 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+mod sections;
+mod types;
+
+pub use sections::*;
+pub use types::*;
+
+use crate::builtins::Blob;
+use crate::io::{
+    require_feature, Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeOptions,
+    DecodeWithDiscriminant, Encode, EncodeError, Feature, IoError, PathItem, PositionReader,
+    Wasmbin,
+};
+use crate::module::MAGIC_AND_VERSION;
+use crate::sections::CustomSection;
+use crate::visit::Visit;
+use crate::wasmbin_discriminants;
+use crate::Module;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+
+const COMPONENT_MAGIC_AND_VERSION: [u8; 8] = [b'\0', b'a', b's', b'm', 0x0D, 0x00, 0x01, 0x00];
+
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ComponentMagicAndVersion;
+
+encode_decode_as!(ComponentMagicAndVersion, {
+    ComponentMagicAndVersion <=> COMPONENT_MAGIC_AND_VERSION,
+}, |actual| {
+    Err(DecodeErrorKind::InvalidMagic {
+        expected: COMPONENT_MAGIC_AND_VERSION,
+        actual,
+    }
+    .into())
+});
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ComponentSection {
+    Custom(Blob<CustomSection>) = 0,
+    CoreModule(Blob<Module>) = 1,
+    CoreInstance(Blob<Vec<CoreInstance>>) = 2,
+    CoreType(Blob<Vec<CoreType>>) = 3,
+    Component(Blob<Component>) = 4,
+    Instance(Blob<Vec<Instance>>) = 5,
+    Alias(Blob<Vec<Alias>>) = 6,
+    Type(Blob<Vec<DefType>>) = 7,
+    Canon(Blob<Vec<Canon>>) = 8,
+    Start(Blob<Start>) = 9,
+    Import(Blob<Vec<ExternDecl>>) = 10,
+    Export(Blob<Vec<Export>>) = 11,
+}
+
+// Unlike module sections, component sections can be repeated and
+// interleaved in any order, so there's no order tracking here.
+impl Encode for [ComponentSection] {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        for section in self {
+            section.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Vec<ComponentSection> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let mut sections = Vec::new();
+        let mut start = r.position();
+        while let Some(disc) = Option::decode(r)? {
+            let i = sections.len();
+            sections.push(
+                ComponentSection::decode_with_discriminant(disc, r)
+                    .map_err(move |err| err.in_path(PathItem::Index(i)).at_position(start))?,
+            );
+            start = r.position();
+        }
+        Ok(sections)
+    }
+}
+
+#[derive(Wasmbin, Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Component {
+    #[doc(hidden)]
+    pub magic_and_version: ComponentMagicAndVersion,
+    pub sections: Vec<ComponentSection>,
+}
+
+impl Component {
+    pub fn decode_from(r: impl crate::io::Read) -> Result<Component, DecodeError> {
+        Self::decode_with_context(r, None)
+    }
+
+    /// Like [`Component::decode_from`], but applies the given options, as
+    /// [`Module::decode_with_options`] does.
+    pub fn decode_with_options(
+        r: impl crate::io::Read,
+        options: &DecodeOptions,
+    ) -> Result<Component, DecodeError> {
+        Self::decode_with_context(r, Some(DecodeContext::new(options)))
+    }
+
+    fn decode_with_context(
+        r: impl crate::io::Read,
+        context: Option<DecodeContext>,
+    ) -> Result<Component, DecodeError> {
+        let mut r = PositionReader::new(r, context);
+        require_feature(&r, Feature::ComponentModel)
+            .map_err(|err| err.in_path(PathItem::Variant("Component")).at_offset(0))?;
+        Self::decode(&mut r).map_err(|err| err.at_offset(r.pos))
+    }
+
+    pub fn encode_into<W: crate::io::Write>(&self, mut w: W) -> Result<W, EncodeError> {
+        self.encode(&mut w)?;
+        Ok(w)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub enum ModuleOrComponent {
+    Module(Module),
+    Component(Component),
+}
+
+/// Reader that yields the already consumed preamble before the rest of the
+/// input.
+struct Replay<'a, R> {
+    preamble: &'a [u8],
+    rest: R,
+}
+
+impl<R: crate::io::Read> crate::io::Read for Replay<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        if self.preamble.is_empty() {
+            return self.rest.read(buf);
+        }
+        let len = self.preamble.len().min(buf.len());
+        let (head, tail) = self.preamble.split_at(len);
+        buf[..len].copy_from_slice(head);
+        self.preamble = tail;
+        Ok(len)
+    }
+}
+
+/// Decodes either a core module or a component, depending on the preamble.
+pub fn decode_any(r: impl crate::io::Read) -> Result<ModuleOrComponent, DecodeError> {
+    decode_any_with_context(r, None)
+}
+
+/// Like [`decode_any`], but applies the given options, as
+/// [`Module::decode_with_options`] does.
+///
+/// Components are rejected unless [`Features::component_model`](crate::io::Features::component_model)
+/// is enabled.
+pub fn decode_any_with_options(
+    r: impl crate::io::Read,
+    options: &DecodeOptions,
+) -> Result<ModuleOrComponent, DecodeError> {
+    decode_any_with_context(r, Some(DecodeContext::new(options)))
+}
+
+fn decode_any_with_context(
+    mut r: impl crate::io::Read,
+    context: Option<DecodeContext>,
+) -> Result<ModuleOrComponent, DecodeError> {
+    let preamble = <[u8; 8]>::decode(&mut r).map_err(|err| err.at_offset(0))?;
+    // Decode the whole thing, preamble included, as a regular module or
+    // component so that paths and offsets match their own `decode_from`.
+    let r = Replay {
+        preamble: &preamble,
+        rest: r,
+    };
+    match preamble {
+        MAGIC_AND_VERSION => Module::decode_with_context(r, context).map(ModuleOrComponent::Module),
+        COMPONENT_MAGIC_AND_VERSION => {
+            Component::decode_with_context(r, context).map(ModuleOrComponent::Component)
+        }
+        actual => Err(DecodeError::from(DecodeErrorKind::InvalidMagic {
+            expected: MAGIC_AND_VERSION,
+            actual,
+        })
+        .at_offset(0)),
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::WasmbinCountable;
+use crate::indices::{
+    ComponentFuncId, ComponentId, ComponentInstanceId, ComponentTypeId, FuncId, InstanceId, MemId,
+    ModuleId, ValueId,
+};
+use crate::io::Wasmbin;
+use crate::visit::Visit;
+use crate::wasmbin_discriminants;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+
+use super::types::{ExternDesc, ExternName, Optional};
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CoreSort {
+    Func = 0x00,
+    Table = 0x01,
+    Memory = 0x02,
+    Global = 0x03,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Tag = 0x04,
+    Type = 0x10,
+    Module = 0x11,
+    Instance = 0x12,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum Sort {
+    Core(CoreSort) = 0x00,
+    Func = 0x01,
+    Value = 0x02,
+    Type = 0x03,
+    Component = 0x04,
+    Instance = 0x05,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CoreSortIdx {
+    pub sort: CoreSort,
+    pub index: u32,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct SortIdx {
+    pub sort: Sort,
+    pub index: u32,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CoreInstantiateArgKind {
+    Instance(InstanceId) = 0x12,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CoreInstantiateArg {
+    pub name: String,
+    pub kind: CoreInstantiateArgKind,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CoreInlineExport {
+    pub name: String,
+    pub sort_idx: CoreSortIdx,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CoreInstance {
+    Instantiate {
+        module: ModuleId,
+        args: Vec<CoreInstantiateArg>,
+    } = 0x00,
+    FromExports(Vec<CoreInlineExport>) = 0x01,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct InstantiateArg {
+    pub name: String,
+    pub sort_idx: SortIdx,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct InlineExport {
+    pub name: ExternName,
+    pub sort_idx: SortIdx,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum Instance {
+    Instantiate {
+        component: ComponentId,
+        args: Vec<InstantiateArg>,
+    } = 0x00,
+    FromExports(Vec<InlineExport>) = 0x01,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum AliasTarget {
+    Export {
+        instance: ComponentInstanceId,
+        name: String,
+    } = 0x00,
+    CoreExport {
+        instance: InstanceId,
+        name: String,
+    } = 0x01,
+    Outer {
+        count: u32,
+        index: u32,
+    } = 0x02,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Alias {
+    pub sort: Sort,
+    pub target: AliasTarget,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CanonOpt {
+    Utf8 = 0x00,
+    Utf16 = 0x01,
+    CompactUtf16 = 0x02,
+    Memory(MemId) = 0x03,
+    Realloc(FuncId) = 0x04,
+    PostReturn(FuncId) = 0x05,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[wasmbin(discriminant = 0x00)]
+pub struct CanonLift {
+    pub core_func: FuncId,
+    pub opts: Vec<CanonOpt>,
+    pub ty: ComponentTypeId,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[wasmbin(discriminant = 0x00)]
+pub struct CanonLower {
+    pub func: ComponentFuncId,
+    pub opts: Vec<CanonOpt>,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum Canon {
+    Lift(CanonLift) = 0x00,
+    Lower(CanonLower) = 0x01,
+    ResourceNew(ComponentTypeId) = 0x02,
+    ResourceDrop(ComponentTypeId) = 0x03,
+    ResourceRep(ComponentTypeId) = 0x04,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Start {
+    pub func: ComponentFuncId,
+    pub args: Vec<ValueId>,
+    pub results: u32,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Export {
+    pub name: ExternName,
+    pub sort_idx: SortIdx,
+    pub desc: Optional<ExternDesc>,
+}
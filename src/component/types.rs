@@ -0,0 +1,317 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::WasmbinCountable;
+use crate::indices::{ComponentTypeId, FuncId, TypeId, ValueId};
+use crate::io::{
+    Decode, DecodeError, DecodeErrorKind, DecodeWithDiscriminant, Encode, EncodeError, PathItem,
+    Wasmbin,
+};
+use crate::sections::{Import as CoreImport, ImportDesc as CoreImportDesc};
+use crate::types::{decode_type_index, FuncType, ValueType};
+use crate::visit::Visit;
+use crate::wasmbin_discriminants;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+
+use super::sections::{Alias, CoreSort};
+
+/// Optional value prefixed with a presence flag, as used throughout
+/// the component binary format.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum Optional<T> {
+    None,
+    Some(T),
+}
+
+impl<T: Encode> Encode for Optional<T> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        match self {
+            Optional::None => 0x00_u8.encode(w),
+            Optional::Some(value) => {
+                0x01_u8.encode(w)?;
+                value.encode(w)
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Optional<T> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        match u8::decode(r)? {
+            0x00 => Ok(Optional::None),
+            0x01 => T::decode(r)
+                .map(Optional::Some)
+                .map_err(|err| err.in_path(PathItem::Variant("Optional::Some"))),
+            discriminant => Err(DecodeErrorKind::UnsupportedDiscriminant {
+                ty: "Optional",
+                discriminant: discriminant.into(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl<T> From<Option<T>> for Optional<T> {
+    fn from(value: Option<T>) -> Self {
+        match value {
+            None => Optional::None,
+            Some(value) => Optional::Some(value),
+        }
+    }
+}
+
+impl<T> From<Optional<T>> for Option<T> {
+    fn from(value: Optional<T>) -> Self {
+        match value {
+            Optional::None => None,
+            Optional::Some(value) => Some(value),
+        }
+    }
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ModuleDecl {
+    Import(CoreImport) = 0x00,
+    Type(CoreType) = 0x01,
+    Alias(CoreAlias) = 0x02,
+    Export { name: String, desc: CoreImportDesc } = 0x03,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CoreAliasTarget {
+    Outer { count: u32, index: u32 } = 0x01,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CoreAlias {
+    pub sort: CoreSort,
+    pub target: CoreAliasTarget,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CoreType {
+    Module(Vec<ModuleDecl>) = 0x50,
+    // Function types already carry their own 0x60 discriminant.
+    Func(FuncType),
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum PrimValType {
+    Bool = 0x7F,
+    S8 = 0x7E,
+    U8 = 0x7D,
+    S16 = 0x7C,
+    U16 = 0x7B,
+    S32 = 0x7A,
+    U32 = 0x79,
+    S64 = 0x78,
+    U64 = 0x77,
+    F32 = 0x76,
+    F64 = 0x75,
+    Char = 0x74,
+    String = 0x73,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum ValType {
+    Prim(PrimValType),
+    Type(ComponentTypeId),
+}
+
+impl Encode for ValType {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        match self {
+            ValType::Prim(ty) => ty.encode(w),
+            ValType::Type(id) => i64::from(id.index).encode(w),
+        }
+    }
+}
+
+impl Decode for ValType {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let discriminant = u8::decode(r)?;
+        if let Some(ty) = PrimValType::maybe_decode_with_discriminant(discriminant, r)? {
+            return Ok(ValType::Prim(ty));
+        }
+        decode_type_index(discriminant, r)
+            .map(|TypeId { index }| ValType::Type(ComponentTypeId { index }))
+            .map_err(|err| err.in_path(PathItem::Variant("ValType::Type")))
+    }
+}
+
+impl WasmbinCountable for ValType {}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct LabeledValType {
+    pub label: String,
+    pub ty: ValType,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Case {
+    pub label: String,
+    pub ty: Optional<ValType>,
+    pub refines: Optional<u32>,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum DefValType {
+    Borrow(ComponentTypeId) = 0x68,
+    Own(ComponentTypeId) = 0x69,
+    Result {
+        ok: Optional<ValType>,
+        err: Optional<ValType>,
+    } = 0x6A,
+    Option(ValType) = 0x6B,
+    Enum(Vec<String>) = 0x6D,
+    Flags(Vec<String>) = 0x6E,
+    Tuple(Vec<ValType>) = 0x6F,
+    List(ValType) = 0x70,
+    Variant(Vec<Case>) = 0x71,
+    Record(Vec<LabeledValType>) = 0x72,
+    // Variants above are kept in ascending order so that the implicit
+    // discriminant of this one doesn't clash with them under `nightly`.
+    Prim(PrimValType),
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ResultList {
+    Unnamed(ValType) = 0x00,
+    Named(Vec<LabeledValType>) = 0x01,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ComponentFuncType {
+    pub params: Vec<LabeledValType>,
+    pub results: ResultList,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ValueBound {
+    Eq(ValueId) = 0x00,
+    Type(ValType) = 0x01,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum TypeBound {
+    Eq(ComponentTypeId) = 0x00,
+    SubResource = 0x01,
+}
+
+/// Reference to a core module type; prefixed with the core module sort.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[wasmbin(discriminant = 0x11)]
+pub struct CoreModuleTypeRef {
+    pub ty: TypeId,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ExternDesc {
+    CoreModule(CoreModuleTypeRef) = 0x00,
+    Func(ComponentTypeId) = 0x01,
+    Value(ValueBound) = 0x02,
+    Type(TypeBound) = 0x03,
+    Component(ComponentTypeId) = 0x04,
+    Instance(ComponentTypeId) = 0x05,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ExternName {
+    Name(String) = 0x00,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ExternDecl {
+    pub name: ExternName,
+    pub desc: ExternDesc,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum InstanceDecl {
+    CoreType(CoreType) = 0x00,
+    Type(DefType) = 0x01,
+    Alias(Alias) = 0x02,
+    Export(ExternDecl) = 0x04,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ComponentDecl {
+    Import(ExternDecl) = 0x03,
+    Instance(InstanceDecl),
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ResourceType {
+    pub rep: ValueType,
+    pub dtor: Optional<FuncId>,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum DefType {
+    Resource(ResourceType) = 0x3F,
+    Func(ComponentFuncType) = 0x40,
+    Component(Vec<ComponentDecl>) = 0x41,
+    Instance(Vec<InstanceDecl>) = 0x42,
+    Value(DefValType),
+}
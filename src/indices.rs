@@ -15,11 +15,13 @@
 use crate::builtins::WasmbinCountable;
 use crate::io::Wasmbin;
 use crate::visit::Visit;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 macro_rules! newtype_id {
     ($name:ident) => {
-        #[derive(PartialEq, Eq, Clone, Copy, Wasmbin, WasmbinCountable, Arbitrary, Hash, Visit)]
+        #[derive(PartialEq, Eq, Clone, Copy, Wasmbin, WasmbinCountable, Hash, Visit)]
+        #[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
         #[repr(transparent)]
         pub struct $name {
             pub index: u32,
@@ -37,8 +39,8 @@ macro_rules! newtype_id {
             }
         }
 
-        impl std::fmt::Debug for $name {
-            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
                 write!(
                     f,
                     "{}#{}",
@@ -52,10 +54,20 @@ macro_rules! newtype_id {
 
 newtype_id!(DataId);
 newtype_id!(ElemId);
+newtype_id!(FieldId);
 newtype_id!(FuncId);
 newtype_id!(GlobalId);
 newtype_id!(LabelId);
 newtype_id!(LocalId);
 newtype_id!(MemId);
 newtype_id!(TableId);
+newtype_id!(TagId);
 newtype_id!(TypeId);
+
+newtype_id!(ComponentId);
+newtype_id!(ComponentFuncId);
+newtype_id!(ComponentInstanceId);
+newtype_id!(ComponentTypeId);
+newtype_id!(InstanceId);
+newtype_id!(ModuleId);
+newtype_id!(ValueId);
@@ -0,0 +1,112 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::indices::{DataId, ElemId, FieldId, LabelId, TypeId};
+use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode, EncodeError, PathItem, Wasmbin};
+use crate::types::{HeapType, RefType};
+use crate::visit::Visit;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+
+const CAST_FLAG_FROM_NULLABLE: u8 = 1 << 0;
+const CAST_FLAG_TO_NULLABLE: u8 = 1 << 1;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct BrOnCast {
+    pub label: LabelId,
+    pub from: RefType,
+    pub to: RefType,
+}
+
+impl Encode for BrOnCast {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        let mut flags = 0;
+        if self.from.nullable {
+            flags |= CAST_FLAG_FROM_NULLABLE;
+        }
+        if self.to.nullable {
+            flags |= CAST_FLAG_TO_NULLABLE;
+        }
+        flags.encode(w)?;
+        self.label.encode(w)?;
+        self.from.heap_type.encode(w)?;
+        self.to.heap_type.encode(w)
+    }
+}
+
+impl Decode for BrOnCast {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let flags = u8::decode(r).map_err(|err| err.in_path(PathItem::Name("flags")))?;
+        if flags & !(CAST_FLAG_FROM_NULLABLE | CAST_FLAG_TO_NULLABLE) != 0 {
+            return Err(DecodeError::from(DecodeErrorKind::UnsupportedDiscriminant {
+                ty: "BrOnCast::flags",
+                discriminant: flags.into(),
+            })
+            .in_path(PathItem::Name("flags")));
+        }
+        let label = LabelId::decode(r).map_err(|err| err.in_path(PathItem::Name("label")))?;
+        let from = HeapType::decode(r).map_err(|err| err.in_path(PathItem::Name("from")))?;
+        let to = HeapType::decode(r).map_err(|err| err.in_path(PathItem::Name("to")))?;
+        Ok(BrOnCast {
+            label,
+            from: RefType {
+                nullable: flags & CAST_FLAG_FROM_NULLABLE != 0,
+                heap_type: from,
+            },
+            to: RefType {
+                nullable: flags & CAST_FLAG_TO_NULLABLE != 0,
+                heap_type: to,
+            },
+        })
+    }
+}
+
+#[crate::wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u32)]
+pub enum GC {
+    StructNew(TypeId) = 0x00,
+    StructNewDefault(TypeId) = 0x01,
+    StructGet { ty: TypeId, field: FieldId } = 0x02,
+    StructGetS { ty: TypeId, field: FieldId } = 0x03,
+    StructGetU { ty: TypeId, field: FieldId } = 0x04,
+    StructSet { ty: TypeId, field: FieldId } = 0x05,
+    ArrayNew(TypeId) = 0x06,
+    ArrayNewDefault(TypeId) = 0x07,
+    ArrayNewFixed { ty: TypeId, size: u32 } = 0x08,
+    ArrayNewData { ty: TypeId, data: DataId } = 0x09,
+    ArrayNewElem { ty: TypeId, elem: ElemId } = 0x0A,
+    ArrayGet(TypeId) = 0x0B,
+    ArrayGetS(TypeId) = 0x0C,
+    ArrayGetU(TypeId) = 0x0D,
+    ArraySet(TypeId) = 0x0E,
+    ArrayLen = 0x0F,
+    ArrayFill(TypeId) = 0x10,
+    ArrayCopy { dest: TypeId, src: TypeId } = 0x11,
+    ArrayInitData { ty: TypeId, data: DataId } = 0x12,
+    ArrayInitElem { ty: TypeId, elem: ElemId } = 0x13,
+    RefTest(HeapType) = 0x14,
+    RefTestNull(HeapType) = 0x15,
+    RefCast(HeapType) = 0x16,
+    RefCastNull(HeapType) = 0x17,
+    BrOnCast(BrOnCast) = 0x18,
+    BrOnCastFail(BrOnCast) = 0x19,
+    AnyConvertExtern = 0x1A,
+    ExternConvertAny = 0x1B,
+    RefI31 = 0x1C,
+    I31GetS = 0x1D,
+    I31GetU = 0x1E,
+}
@@ -15,10 +15,12 @@
 use crate::indices::{DataId, ElemId, MemId, TableId};
 use crate::io::Wasmbin;
 use crate::visit::Visit;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 #[crate::wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u32)]
 pub enum Misc {
     I32TruncSatF32S = 0x00,
@@ -13,37 +13,58 @@
 // limitations under the License.
 
 use crate::builtins::FloatConst;
+use crate::builtins::WasmbinCountable;
+use crate::indices::TagId;
 use crate::indices::{FuncId, GlobalId, LabelId, LocalId, MemId, TableId, TypeId};
-use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
-use crate::types::{BlockType, RefType, ValueType};
+use crate::io::{
+    get_limit, require_feature, track_allocation, Decode, DecodeError, DecodeErrorKind,
+    DecodeWithDiscriminant, Encode, EncodeError, EncodeErrorKind, Feature, Limit, PathItem,
+    Wasmbin,
+};
+use crate::types::{BlockType, HeapType, ValueType};
 use crate::visit::Visit;
 use crate::wasmbin_discriminants;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
-use thiserror::Error;
+use core::convert::TryFrom;
 
 const OP_CODE_BLOCK_START: u8 = 0x02;
 const OP_CODE_LOOP_START: u8 = 0x03;
 const OP_CODE_IF_START: u8 = 0x04;
 const OP_CODE_END: u8 = 0x0B;
+const OP_CODE_TRY_START: u8 = 0x06;
+const OP_CODE_DELEGATE: u8 = 0x18;
+const OP_CODE_TRY_TABLE_START: u8 = 0x1F;
 
-#[derive(Debug, Error)]
-#[error("Mismatched block depth")]
+#[derive(Debug)]
 struct DepthError;
 
-impl From<DepthError> for std::io::Error {
-    fn from(err: DepthError) -> Self {
-        Self::new(std::io::ErrorKind::InvalidData, err)
+impl From<DepthError> for crate::io::IoError {
+    fn from(_err: DepthError) -> Self {
+        Self::new(
+            crate::io::IoErrorKind::InvalidData,
+            "Mismatched block depth",
+        )
     }
 }
 
 #[derive(Default)]
 struct DepthTracker {
-    depth: u32,
+    depth: usize,
 }
 
 impl DepthTracker {
-    pub fn inc(&mut self) {
+    pub fn inc(&mut self, max: usize) -> Result<(), DecodeError> {
         self.depth += 1;
+        if self.depth > max {
+            return Err(DecodeErrorKind::LimitExceeded {
+                limit: Limit::NestingDepth,
+                max,
+            }
+            .into());
+        }
+        Ok(())
     }
 
     // Returns a bool indicating whether to continue, or an error state.
@@ -51,61 +72,104 @@ impl DepthTracker {
         self.depth = self.depth.checked_sub(1).ok_or(DepthError)?;
         Ok(())
     }
-
-    pub fn assert_end(self) -> Result<(), DepthError> {
-        match self.depth {
-            0 => Ok(()),
-            _ => Err(DepthError),
-        }
-    }
 }
 
 impl Encode for [Instruction] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        let mut depth_tracker = DepthTracker::default();
-        for instr in self {
+    #[allow(clippy::collapsible_match)]
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        let mut open_blocks = Vec::new();
+        for (i, instr) in self.iter().enumerate() {
             match instr {
                 Instruction::BlockStart(_)
                 | Instruction::LoopStart(_)
-                | Instruction::IfStart(_) => {
-                    depth_tracker.inc();
+                | Instruction::IfStart(_)
+                | Instruction::TryStart(_)
+                | Instruction::TryTableStart { .. } => {
+                    open_blocks.push(i);
                 }
-                Instruction::End => {
-                    depth_tracker.try_dec()?;
+                // `delegate` closes its `try` block instead of `end`.
+                Instruction::End | Instruction::Delegate(_) => {
+                    if open_blocks.pop().is_none() {
+                        return Err(EncodeError::from(EncodeErrorKind::UnexpectedEnd)
+                            .in_path(PathItem::Index(i)));
+                    }
                 }
                 _ => {}
             }
-            instr.encode(w)?;
+            instr
+                .encode(w)
+                .map_err(|err| err.in_path(PathItem::Index(i)))?;
+        }
+        if let Some(&i) = open_blocks.last() {
+            return Err(
+                EncodeError::from(EncodeErrorKind::UnclosedBlock).in_path(PathItem::Index(i))
+            );
         }
-        depth_tracker.assert_end()?;
         OP_CODE_END.encode(w)
     }
 }
 
-impl Decode for Vec<Instruction> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let mut res = Vec::new();
-        let mut depth_tracker = DepthTracker::default();
-        loop {
-            let op_code = u8::decode(r)?;
-            match op_code {
-                OP_CODE_BLOCK_START | OP_CODE_LOOP_START | OP_CODE_IF_START => {
-                    depth_tracker.inc();
-                }
-                OP_CODE_END => {
-                    if depth_tracker.try_dec().is_err() {
-                        break;
-                    }
+/// Decodes an expression, invoking `before_instr` with the reader right
+/// before each instruction, including the final `end`.
+#[allow(clippy::collapsible_match)]
+pub(crate) fn decode_with_callback<R: crate::io::Read>(
+    r: &mut R,
+    mut before_instr: impl FnMut(&R),
+) -> Result<Expression, DecodeError> {
+    let mut res = Vec::new();
+    let mut depth_tracker = DepthTracker::default();
+    let max_instructions = get_limit(r, Limit::Instructions);
+    let max_nesting_depth = get_limit(r, Limit::NestingDepth);
+    loop {
+        before_instr(r);
+        let start = r.position();
+        let op_code = u8::decode(r)?;
+        match op_code {
+            OP_CODE_BLOCK_START
+            | OP_CODE_LOOP_START
+            | OP_CODE_IF_START
+            | OP_CODE_TRY_START
+            | OP_CODE_TRY_TABLE_START => {
+                depth_tracker.inc(max_nesting_depth)?;
+            }
+            OP_CODE_END => {
+                if depth_tracker.try_dec().is_err() {
+                    break;
                 }
-                _ => {}
             }
-            let i = res.len();
-            res.push(
-                Instruction::decode_with_discriminant(op_code, r)
-                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
-            );
+            // Unlike `end`, `delegate` can never terminate the expression itself.
+            OP_CODE_DELEGATE => {
+                depth_tracker.try_dec().map_err(crate::io::IoError::from)?;
+            }
+            _ => {}
         }
-        Ok(res)
+        let i = res.len();
+        if i >= max_instructions {
+            return Err(DecodeErrorKind::LimitExceeded {
+                limit: Limit::Instructions,
+                max: max_instructions,
+            }
+            .into());
+        }
+        let instr = Instruction::decode_with_discriminant(op_code, r)
+            .map_err(move |err| err.in_path(PathItem::Index(i)).at_position(start))?;
+        if res.len() == res.capacity() {
+            // Account for the growth before it happens.
+            let additional = res.capacity().max(8);
+            track_allocation(
+                r,
+                additional.saturating_mul(core::mem::size_of::<Instruction>()),
+            )?;
+            res.reserve_exact(additional);
+        }
+        res.push(instr);
+    }
+    Ok(res)
+}
+
+impl Decode for Vec<Instruction> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        decode_with_callback(r, |_| {})
     }
 }
 
@@ -113,20 +177,95 @@ pub type Expression = Vec<Instruction>;
 
 impl crate::builtins::WasmbinCountable for Expression {}
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+// Multi-memory proposal reuses bit 6 of the alignment field to signal
+// that an explicit memory index follows.
+const MEM_ARG_MEMORY_FLAG: u32 = 1 << 6;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
 pub struct MemArg {
     pub align: u32,
-    pub offset: u32,
+    pub memory: Option<MemId>,
+    /// Offsets that don't fit in 32 bits are only valid for memories
+    /// indexed by `i64` from the memory64 proposal.
+    pub offset: u64,
+}
+
+impl Encode for MemArg {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        if self.align & MEM_ARG_MEMORY_FLAG != 0 {
+            return Err(EncodeErrorKind::InvalidValue(
+                "Alignment conflicts with the memory index flag",
+            )
+            .into());
+        }
+        match self.memory {
+            None => self.align.encode(w)?,
+            Some(memory) => {
+                (self.align | MEM_ARG_MEMORY_FLAG).encode(w)?;
+                memory.encode(w)?;
+            }
+        }
+        self.offset.encode(w)
+    }
+}
+
+// The memory index flag can't be set in the alignment itself, so mask it
+// off to avoid generating arguments that can't be encoded.
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for MemArg {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(MemArg {
+            align: u32::arbitrary(u)? & !MEM_ARG_MEMORY_FLAG,
+            memory: u.arbitrary()?,
+            offset: u.arbitrary()?,
+        })
+    }
 }
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+impl Decode for MemArg {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let align = u32::decode(r).map_err(|err| err.in_path(PathItem::Name("align")))?;
+        let memory = match align & MEM_ARG_MEMORY_FLAG {
+            0 => None,
+            _ => Some(MemId::decode(r).map_err(|err| err.in_path(PathItem::Name("memory")))?),
+        };
+        let offset = (|| {
+            let offset = u64::decode(r)?;
+            if u32::try_from(offset).is_err() {
+                require_feature(r, Feature::Memory64)?;
+            }
+            Ok(offset)
+        })()
+        .map_err(|err: DecodeError| err.in_path(PathItem::Name("offset")))?;
+        Ok(MemArg {
+            align: align & !MEM_ARG_MEMORY_FLAG,
+            memory,
+            offset,
+        })
+    }
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct CallIndirect {
     ty: TypeId,
     table: TableId,
 }
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum Catch {
+    One { tag: TagId, label: LabelId } = 0x00,
+    OneRef { tag: TagId, label: LabelId } = 0x01,
+    All { label: LabelId } = 0x02,
+    AllRef { label: LabelId } = 0x03,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum Instruction {
     Unreachable = 0x00,
@@ -135,6 +274,16 @@ pub enum Instruction {
     LoopStart(BlockType) = OP_CODE_LOOP_START,
     IfStart(BlockType) = OP_CODE_IF_START,
     IfElse = 0x05,
+    #[wasmbin(requires = Feature::Exceptions)]
+    TryStart(BlockType) = OP_CODE_TRY_START,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Catch(TagId) = 0x07,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Throw(TagId) = 0x08,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Rethrow(LabelId) = 0x09,
+    #[wasmbin(requires = Feature::Exceptions)]
+    ThrowRef = 0x0A,
     End = OP_CODE_END,
     Br(LabelId) = 0x0C,
     BrIf(LabelId) = 0x0D,
@@ -145,13 +294,27 @@ pub enum Instruction {
     Return = 0x0F,
     Call(FuncId) = 0x10,
     CallIndirect(CallIndirect) = 0x11,
-    #[cfg(feature = "tail-call")]
+    #[wasmbin(requires = Feature::TailCall)]
     ReturnCall(FuncId) = 0x12,
-    #[cfg(feature = "tail-call")]
+    #[wasmbin(requires = Feature::TailCall)]
     ReturnCallIndirect(CallIndirect) = 0x13,
+    #[wasmbin(requires = Feature::FunctionReferences)]
+    CallRef(TypeId) = 0x14,
+    #[wasmbin(requires = Feature::FunctionReferences)]
+    #[wasmbin(requires = Feature::TailCall)]
+    ReturnCallRef(TypeId) = 0x15,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Delegate(LabelId) = OP_CODE_DELEGATE,
+    #[wasmbin(requires = Feature::Exceptions)]
+    CatchAll = 0x19,
     Drop = 0x1A,
     Select = 0x1B,
     SelectWithTypes(Vec<ValueType>) = 0x1C,
+    #[wasmbin(requires = Feature::Exceptions)]
+    TryTableStart {
+        ty: BlockType,
+        catches: Vec<Catch>,
+    } = OP_CODE_TRY_TABLE_START,
     LocalGet(LocalId) = 0x20,
     LocalSet(LocalId) = 0x21,
     LocalTee(LocalId) = 0x22,
@@ -316,13 +479,23 @@ pub enum Instruction {
     I64Extend8S = 0xC2,
     I64Extend16S = 0xC3,
     I64Extend32S = 0xC4,
-    RefNull(RefType) = 0xD0,
+    RefNull(HeapType) = 0xD0,
     RefIsNull = 0xD1,
     RefFunc(FuncId) = 0xD2,
+    #[wasmbin(requires = Feature::Gc)]
+    RefEq = 0xD3,
+    #[wasmbin(requires = Feature::FunctionReferences)]
+    RefAsNonNull = 0xD4,
+    #[wasmbin(requires = Feature::FunctionReferences)]
+    BrOnNull(LabelId) = 0xD5,
+    #[wasmbin(requires = Feature::FunctionReferences)]
+    BrOnNonNull(LabelId) = 0xD6,
+    #[wasmbin(requires = Feature::Gc)]
+    GC(GC) = 0xFB,
     Misc(Misc) = 0xFC,
-    #[cfg(feature = "simd")]
+    #[wasmbin(requires = Feature::Simd)]
     SIMD(SIMD) = 0xFD,
-    #[cfg(feature = "threads")]
+    #[wasmbin(requires = Feature::Threads)]
     Atomic(Atomic) = 0xFE,
 }
 
@@ -330,14 +503,14 @@ mod misc;
 
 pub use misc::Misc;
 
-#[cfg(feature = "simd")]
+pub mod gc;
+
+pub use gc::GC;
+
 pub mod simd;
 
-#[cfg(feature = "simd")]
 pub use simd::SIMD;
 
-#[cfg(feature = "threads")]
 pub mod threads;
 
-#[cfg(feature = "threads")]
 pub use threads::Atomic;
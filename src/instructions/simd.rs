@@ -13,9 +13,10 @@
 // limitations under the License.
 
 use super::MemArg;
-use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode, Wasmbin};
+use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode, EncodeError, Wasmbin};
 use crate::visit::Visit;
 use crate::wasmbin_discriminants;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 macro_rules! def_lane_idx {
@@ -25,13 +26,13 @@ macro_rules! def_lane_idx {
         pub struct $name(u8);
 
         impl Encode for $name {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
                 self.0.encode(w)
             }
         }
 
         impl Decode for $name {
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
                 let value = u8::decode(r)?;
                 if value >= $num {
                     return Err(DecodeErrorKind::UnsupportedDiscriminant {
@@ -44,6 +45,7 @@ macro_rules! def_lane_idx {
             }
         }
 
+        #[cfg(feature = "arbitrary")]
         impl<'a> Arbitrary<'a> for $name {
             #[allow(clippy::range_minus_one)]
             fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
@@ -60,13 +62,13 @@ def_lane_idx!(LaneIdx16, 16);
 def_lane_idx!(LaneIdx32, 32);
 
 impl Encode for [LaneIdx32; 16] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
-        unsafe { &*(self as *const [LaneIdx32; 16] as *const [u8; 16]) }.encode(w)
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        unsafe { &*core::ptr::from_ref(self).cast::<[u8; 16]>() }.encode(w)
     }
 }
 
 impl Decode for [LaneIdx32; 16] {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         let bytes = <[u8; 16]>::decode(r)?;
         for &b in &bytes {
             if b >= 32 {
@@ -77,7 +79,7 @@ impl Decode for [LaneIdx32; 16] {
                 .into());
             }
         }
-        Ok(unsafe { std::mem::transmute::<[u8; 16], [LaneIdx32; 16]>(bytes) })
+        Ok(unsafe { core::mem::transmute::<[u8; 16], [LaneIdx32; 16]>(bytes) })
     }
 }
 
@@ -85,7 +87,8 @@ impl_visit_for_iter!([u8; 16]);
 impl_visit_for_iter!([LaneIdx32; 16]);
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u32)]
 pub enum SIMD {
     V128Load(MemArg) = 0x00,
@@ -324,4 +327,44 @@ pub enum SIMD {
     I16x8ExtaddPairwiseI8x16U = 0x7D,
     I32x4ExtaddPairwiseI16x8S = 0x7E,
     I32x4ExtaddPairwiseI16x8U = 0x7F,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I8x16RelaxedSwizzle = 0x100,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedTruncF32x4S = 0x101,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedTruncF32x4U = 0x102,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedTruncF64x2SZero = 0x103,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedTruncF64x2UZero = 0x104,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F32x4RelaxedMadd = 0x105,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F32x4RelaxedNmadd = 0x106,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F64x2RelaxedMadd = 0x107,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F64x2RelaxedNmadd = 0x108,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I8x16RelaxedLaneselect = 0x109,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I16x8RelaxedLaneselect = 0x10A,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedLaneselect = 0x10B,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I64x2RelaxedLaneselect = 0x10C,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F32x4RelaxedMin = 0x10D,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F32x4RelaxedMax = 0x10E,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F64x2RelaxedMin = 0x10F,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    F64x2RelaxedMax = 0x110,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I16x8RelaxedQ15mulrS = 0x111,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I16x8RelaxedDotI8x16I7x16S = 0x112,
+    #[wasmbin(requires = Feature::RelaxedSimd)]
+    I32x4RelaxedDotI8x16I7x16AddS = 0x113,
 }
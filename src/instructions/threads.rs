@@ -13,17 +13,20 @@
 // limitations under the License.
 
 use super::MemArg;
+use crate::indices::MemId;
 use crate::io::{DecodeErrorKind, Wasmbin};
 use crate::visit::Visit;
 use crate::wasmbin_discriminants;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
 
 macro_rules! def_mem_arg {
 	($name:ident, $num:literal) => {
-		#[derive(Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
-		#[repr(transparent)]
+		#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+		#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 		pub struct $name {
-			pub offset: u32,
+			pub memory: Option<MemId>,
+			pub offset: u64,
 		}
 
 		impl $name {
@@ -34,17 +37,18 @@ macro_rules! def_mem_arg {
 			fn from(arg: $name) -> MemArg {
 				MemArg {
 					align: $num,
+					memory: arg.memory,
 					offset: arg.offset,
 				}
 			}
 		}
 
 		encode_decode_as!($name, {
-			($name { offset }) <=> (MemArg { align: $num, offset }),
+			($name { memory, offset }) <=> (MemArg { align: $num, memory, offset }),
 		}, |arg| {
 			Err(DecodeErrorKind::UnsupportedDiscriminant {
 				ty: stringify!($name),
-				discriminant: arg.offset.into(),
+				discriminant: arg.align.into(),
 			}.into())
 		});
 	};
@@ -56,7 +60,8 @@ def_mem_arg!(MemArg32, 0x02);
 def_mem_arg!(MemArg64, 0x03);
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum Atomic {
     Wake(MemArg32) = 0x00,
@@ -126,3 +131,61 @@ pub enum Atomic {
     I64Rmw16CmpXchgU(MemArg16) = 0x4D,
     I64Rmw32CmpXchgU(MemArg32) = 0x4E,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{Decode, DecodeError, Encode, SliceReader};
+    use alloc::vec::Vec;
+
+    fn decode<T: Decode>(bytes: &[u8]) -> Result<T, DecodeError> {
+        T::decode(&mut SliceReader::new(bytes, None, None))
+    }
+
+    #[test]
+    fn mem_arg_with_other_alignment_is_rejected() {
+        // Alignment 2 and offset 5.
+        let err = decode::<MemArg16>(&[0x02, 0x05]).unwrap_err();
+        assert!(matches!(
+            err.kind,
+            DecodeErrorKind::UnsupportedDiscriminant {
+                ty: "MemArg16",
+                discriminant: 2,
+            }
+        ));
+        assert_eq!(
+            decode::<MemArg32>(&[0x02, 0x05]).unwrap(),
+            MemArg32 {
+                memory: None,
+                offset: 5,
+            }
+        );
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn arbitrary_mem_args_round_trip() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        fn check<'a, T: Arbitrary<'a> + Decode + Encode + PartialEq + core::fmt::Debug>(
+            u: &mut Unstructured<'a>,
+        ) {
+            let arg = T::arbitrary(u).unwrap();
+            let mut buf = Vec::new();
+            arg.encode(&mut buf).unwrap();
+            assert_eq!(decode::<T>(&buf).unwrap(), arg);
+        }
+
+        let seed: Vec<u8> = (0..4096_u32)
+            .map(|i| i.wrapping_mul(2_654_435_761).to_be_bytes()[1])
+            .collect();
+        let mut u = Unstructured::new(&seed);
+        while !u.is_empty() {
+            check::<MemArg>(&mut u);
+            check::<MemArg8>(&mut u);
+            check::<MemArg16>(&mut u);
+            check::<MemArg32>(&mut u);
+            check::<MemArg64>(&mut u);
+        }
+    }
+}
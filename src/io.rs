@@ -13,19 +13,281 @@
 // limitations under the License.
 
 use crate::sections::SectionOrderError;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use thiserror::Error;
 pub use wasmbin_derive::Wasmbin;
 
+#[cfg(feature = "std")]
+pub use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+
+/// Subset of `std::io::ErrorKind` used by this crate without `std`.
+#[cfg(not(feature = "std"))]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IoErrorKind {
+    InvalidData,
+    Interrupted,
+    UnexpectedEof,
+    Other,
+}
+
+/// Replacement for `std::io::Error` without `std`.
+#[cfg(not(feature = "std"))]
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct IoError {
+    kind: IoErrorKind,
+    message: &'static str,
+}
+
+#[cfg(not(feature = "std"))]
+impl IoError {
+    pub fn new(kind: IoErrorKind, message: &'static str) -> Self {
+        IoError { kind, message }
+    }
+
+    pub fn kind(&self) -> IoErrorKind {
+        self.kind
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl From<IoErrorKind> for IoError {
+    fn from(kind: IoErrorKind) -> Self {
+        // Same messages as in `std`.
+        let message = match kind {
+            IoErrorKind::InvalidData => "invalid data",
+            IoErrorKind::Interrupted => "operation interrupted",
+            IoErrorKind::UnexpectedEof => "unexpected end of file",
+            IoErrorKind::Other => "other error",
+        };
+        IoError::new(kind, message)
+    }
+}
+
+/// Source of bytes for [`Decode`].
+///
+/// With the `std` feature, it's implemented for all `std::io::Read` types.
+/// Otherwise, it's implemented for `&[u8]` and `&mut R`.
+pub trait Read {
+    /// Pulls some bytes into `buf`, returning how many were read, or 0 at
+    /// the end of input.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError>;
+
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), IoError> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => return Err(IoErrorKind::UnexpectedEof.into()),
+                Ok(n) => buf = &mut buf[n..],
+                Err(err) if err.kind() == IoErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads all the remaining bytes into `buf`, returning how many were
+    /// read.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        let start = buf.len();
+        let mut chunk = [0; 256];
+        loop {
+            match self.read(&mut chunk) {
+                Ok(0) => return Ok(buf.len() - start),
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == IoErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Reads a single byte.
+    ///
+    /// Most of the input is decoded byte by byte, so in-memory readers
+    /// override this to skip the generic [`Read::read_exact`] machinery.
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        let mut byte = 0;
+        self.read_exact(core::slice::from_mut(&mut byte))?;
+        Ok(byte)
+    }
+
+    /// Like [`Read::read_byte`], but returns `None` at the end of input.
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let mut byte = 0;
+        loop {
+            return match self.read(core::slice::from_mut(&mut byte)) {
+                Ok(0) => Ok(None),
+                Ok(_) => Ok(Some(byte)),
+                Err(err) if err.kind() == IoErrorKind::Interrupted => continue,
+                Err(err) => Err(err),
+            };
+        }
+    }
+
+    /// Offset of the next byte from the start of the whole input, if known.
+    ///
+    /// Used to report where failed items start and where lazily decoded
+    /// contents are located in the input.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+
+    /// Options of the decoding in progress, if it has any.
+    ///
+    /// Readers that wrap other readers forward it, so that options passed
+    /// to a top-level entry point such as
+    /// [`Module::decode_with_options`](crate::Module::decode_with_options)
+    /// reach every decoder.
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        None
+    }
+}
+
+/// Destination for [`Encode`].
+///
+/// With the `std` feature, it's implemented for all `std::io::Write`
+/// types. Otherwise, it's implemented for `Vec<u8>` and `&mut W`.
+pub trait Write {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError>;
+}
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read + ?Sized> Read for R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        std::io::Read::read(self, buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        std::io::Read::read_exact(self, buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        std::io::Read::read_to_end(self, buf)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write + ?Sized> Write for W {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        std::io::Write::write_all(self, buf)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Read for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let n = buf.len().min(self.len());
+        let (head, tail) = self.split_at(n);
+        buf[..n].copy_from_slice(head);
+        *self = tail;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        if buf.len() > self.len() {
+            *self = &[];
+            return Err(IoErrorKind::UnexpectedEof.into());
+        }
+        let (head, tail) = self.split_at(buf.len());
+        buf.copy_from_slice(head);
+        *self = tail;
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        let n = self.len();
+        buf.extend_from_slice(self);
+        *self = &[];
+        Ok(n)
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        self.try_read_byte()?
+            .ok_or_else(|| IoErrorKind::UnexpectedEof.into())
+    }
+
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        Ok(self.split_first().map(|(&byte, rest)| {
+            *self = rest;
+            byte
+        }))
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<R: Read + ?Sized> Read for &mut R {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        (**self).read(buf)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        (**self).read_exact(buf)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        (**self).read_to_end(buf)
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        (**self).read_byte()
+    }
+
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        (**self).try_read_byte()
+    }
+
+    fn position(&self) -> Option<usize> {
+        (**self).position()
+    }
+
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        (**self).decode_context()
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for Vec<u8> {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        self.extend_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl<W: Write + ?Sized> Write for &mut W {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        (**self).write_all(buf)
+    }
+}
+
+/// Error from decoding a LEB128-encoded integer.
+#[derive(Error, Debug)]
+pub enum Leb128Error {
+    #[error("{0}")]
+    IoError(#[from] IoError),
+
+    #[error("The number being read is larger than can be represented")]
+    Overflow,
+}
+
 #[derive(Error, Debug)]
 pub enum DecodeErrorKind {
     #[error("{0}")]
-    Io(#[from] std::io::Error),
+    Io(#[from] IoError),
 
     #[error("{0}")]
-    Leb128(#[from] leb128::read::Error),
+    Leb128(#[from] Leb128Error),
 
     #[error("{0}")]
-    Utf8(#[from] std::string::FromUtf8Error),
+    Utf8(#[from] alloc::string::FromUtf8Error),
 
     #[error("Could not recognise discriminant 0x{discriminant:X} for type {ty}")]
     UnsupportedDiscriminant {
@@ -33,78 +295,682 @@ pub enum DecodeErrorKind {
         discriminant: i128,
     },
 
-    #[error("Invalid module magic signature [{actual:02X?}]")]
-    InvalidMagic { actual: [u8; 8] },
+    #[error("Invalid module magic signature {actual:02X?}, expected {expected:02X?}")]
+    InvalidMagic { expected: [u8; 8], actual: [u8; 8] },
 
     #[error("Unrecognized data")]
     UnrecognizedData,
 
     #[error("{0}")]
     SectionOutOfOrder(#[from] SectionOrderError),
+
+    #[error("Exceeded the limit on {limit} ({max})")]
+    LimitExceeded { limit: Limit, max: usize },
+
+    #[error("Feature `{0}` is not enabled")]
+    FeatureNotEnabled(Feature),
 }
 
-#[derive(Debug)]
-pub(crate) enum PathItem {
+/// Step on the path from the root of a decoded value to a nested item.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum PathItem {
+    /// Field of a struct or of an enum variant.
     Name(&'static str),
+    /// Element of a sequence.
     Index(usize),
+    /// Type or enum variant that's being decoded, e.g. `Section::Code`.
     Variant(&'static str),
 }
 
+impl core::fmt::Display for PathItem {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match *self {
+            PathItem::Name(name) => write!(f, ".{name}"),
+            PathItem::Index(index) => write!(f, "[{index}]"),
+            PathItem::Variant(variant) => write!(f, ":<{variant}>"),
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub struct DecodeError {
     path: Vec<PathItem>,
+    offset: Option<usize>,
     #[source]
     pub kind: DecodeErrorKind,
 }
 
 impl DecodeError {
-    pub(crate) fn in_path(mut self, item: PathItem) -> Self {
+    /// Records that the error happened inside `item`.
+    ///
+    /// Used by derived implementations, as well as manual ones, to build up
+    /// the [`path`](Self::path) from the innermost item outwards.
+    #[must_use]
+    pub fn in_path(mut self, item: PathItem) -> Self {
         self.path.push(item);
         self
     }
+
+    /// Records the offset of the failure, unless an inner item already did.
+    pub(crate) fn at_offset(mut self, offset: usize) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+
+    /// Like [`DecodeError::at_offset`], but only if the position is known.
+    pub(crate) fn at_position(self, pos: Option<usize>) -> Self {
+        match pos {
+            Some(pos) => self.at_offset(pos),
+            None => self,
+        }
+    }
+
+    /// Path from the root of the decoded value to the item that failed.
+    pub fn path(&self) -> impl DoubleEndedIterator<Item = &PathItem> + ExactSizeIterator {
+        self.path.iter().rev()
+    }
+
+    /// Byte offset in the input at which decoding failed.
+    ///
+    /// Points to the start of the innermost failed sequence element,
+    /// section or instruction, or else to where reading stopped. It's known
+    /// for anything decoded by top-level entry points such as
+    /// [`Module::decode_from`](crate::Module::decode_from), including
+    /// [`Lazy`](crate::builtins::Lazy) contents decoded on demand later,
+    /// but not for values constructed from raw bytes.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
 }
 
 impl<E: Into<DecodeErrorKind>> From<E> for DecodeError {
     fn from(err: E) -> DecodeError {
         DecodeError {
             path: vec![],
+            offset: None,
             kind: err.into(),
         }
     }
 }
 
-impl std::fmt::Display for DecodeError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.write_str("(root)")?;
-        for item in self.path.iter().rev() {
-            match *item {
-                PathItem::Name(name) => write!(f, ".{}", name),
-                PathItem::Index(index) => write!(f, "[{}]", index),
-                PathItem::Variant(variant) => write!(f, ":<{}>", variant),
-            }?;
+fn fmt_path(path: &[PathItem], f: &mut core::fmt::Formatter) -> core::fmt::Result {
+    f.write_str("(root)")?;
+    for item in path.iter().rev() {
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+impl core::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt_path(&self.path, f)?;
+        write!(f, ": {}", self.kind)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset 0x{offset:X}")?;
         }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum EncodeErrorKind {
+    #[error("{0}")]
+    Io(#[from] IoError),
+
+    #[error("{0}")]
+    IntegerOverflow(#[from] core::num::TryFromIntError),
+
+    #[error("{0}")]
+    SectionOutOfOrder(#[from] SectionOrderError),
+
+    #[error("Block is not closed by a matching `end`")]
+    UnclosedBlock,
+
+    #[error("`end` doesn't have a matching block")]
+    UnexpectedEnd,
+
+    #[error("{0}")]
+    InvalidValue(&'static str),
+}
+
+#[derive(Error, Debug)]
+pub struct EncodeError {
+    path: Vec<PathItem>,
+    #[source]
+    pub kind: EncodeErrorKind,
+}
+
+impl EncodeError {
+    /// Records that the error happened inside `item`.
+    ///
+    /// Used by derived implementations, as well as manual ones, to build up
+    /// the [`path`](Self::path) from the innermost item outwards.
+    #[must_use]
+    pub fn in_path(mut self, item: PathItem) -> Self {
+        self.path.push(item);
+        self
+    }
+
+    /// Path from the root of the encoded value to the item that failed.
+    pub fn path(&self) -> impl DoubleEndedIterator<Item = &PathItem> + ExactSizeIterator {
+        self.path.iter().rev()
+    }
+}
+
+impl<E: Into<EncodeErrorKind>> From<E> for EncodeError {
+    fn from(err: E) -> EncodeError {
+        EncodeError {
+            path: vec![],
+            kind: err.into(),
+        }
+    }
+}
+
+impl core::fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt_path(&self.path, f)?;
         write!(f, ": {}", self.kind)
     }
 }
 
-impl From<std::num::TryFromIntError> for DecodeErrorKind {
-    fn from(_err: std::num::TryFromIntError) -> Self {
-        DecodeErrorKind::Leb128(leb128::read::Error::Overflow)
+impl From<core::convert::Infallible> for EncodeErrorKind {
+    fn from(err: core::convert::Infallible) -> Self {
+        match err {}
+    }
+}
+
+/// Reader that keeps track of the number of bytes read so far.
+///
+/// Also carries the context of top-level decodings.
+pub(crate) struct PositionReader<R> {
+    inner: R,
+    pub pos: usize,
+    context: Option<DecodeContext>,
+}
+
+impl<R> PositionReader<R> {
+    pub fn new(inner: R, context: Option<DecodeContext>) -> Self {
+        PositionReader {
+            inner,
+            pos: 0,
+            context,
+        }
+    }
+}
+
+// Implemented directly rather than via `std::io::Read` so that it can
+// provide the position.
+impl<R: Read> Read for PositionReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let n = self.inner.read(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        self.inner.read_exact(buf)?;
+        self.pos += buf.len();
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        let n = self.inner.read_to_end(buf)?;
+        self.pos += n;
+        Ok(n)
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        let byte = self.inner.read_byte()?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let byte = self.inner.try_read_byte()?;
+        self.pos += usize::from(byte.is_some());
+        Ok(byte)
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.pos)
+    }
+
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        self.context
+            .as_ref()
+            .or_else(|| self.inner.decode_context())
+    }
+}
+
+/// Reader over in-memory input, such as contents of lazily decoded values.
+///
+/// With `std`, `&[u8]` only implements [`Read`] via `std::io::Read`, so
+/// this is the one that reads single bytes straight from the slice.
+pub(crate) struct SliceReader<'a> {
+    bytes: &'a [u8],
+    /// Number of bytes read so far.
+    pos: usize,
+    /// Offset of `bytes` in the whole input, if known.
+    offset: Option<usize>,
+    context: Option<&'a DecodeContext>,
+}
+
+impl<'a> SliceReader<'a> {
+    pub fn new(bytes: &'a [u8], offset: Option<usize>, context: Option<&'a DecodeContext>) -> Self {
+        SliceReader {
+            bytes,
+            pos: 0,
+            offset,
+            context,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    /// Bytes that weren't read yet.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.pos..]
     }
 }
 
-impl From<std::convert::Infallible> for DecodeErrorKind {
-    fn from(err: std::convert::Infallible) -> Self {
+impl Read for SliceReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        let rest = self.rest();
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), IoError> {
+        let src = self
+            .rest()
+            .get(..buf.len())
+            .ok_or(IoErrorKind::UnexpectedEof)?;
+        buf.copy_from_slice(src);
+        self.pos += buf.len();
+        Ok(())
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize, IoError> {
+        let rest = self.rest();
+        buf.extend_from_slice(rest);
+        self.pos = self.bytes.len();
+        Ok(rest.len())
+    }
+
+    #[inline]
+    fn read_byte(&mut self) -> Result<u8, IoError> {
+        let byte = *self.bytes.get(self.pos).ok_or(IoErrorKind::UnexpectedEof)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    #[inline]
+    fn try_read_byte(&mut self) -> Result<Option<u8>, IoError> {
+        let byte = self.bytes.get(self.pos).copied();
+        self.pos += usize::from(byte.is_some());
+        Ok(byte)
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.offset? + self.pos)
+    }
+
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        self.context
+    }
+}
+
+impl From<core::num::TryFromIntError> for DecodeErrorKind {
+    fn from(_err: core::num::TryFromIntError) -> Self {
+        DecodeErrorKind::Leb128(Leb128Error::Overflow)
+    }
+}
+
+impl From<core::convert::Infallible> for DecodeErrorKind {
+    fn from(err: core::convert::Infallible) -> Self {
         match err {}
     }
 }
 
+/// Writer that only counts the number of bytes written to it.
+#[derive(Default)]
+struct ByteCounter {
+    len: usize,
+}
+
+#[cfg(feature = "std")]
+impl std::io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.len += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for ByteCounter {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), IoError> {
+        self.len += buf.len();
+        Ok(())
+    }
+}
+
+/// Kind of resource restricted by `DecodeLimits`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Limit {
+    Sections,
+    VecLen,
+    Locals,
+    Instructions,
+    NestingDepth,
+    AllocatedBytes,
+}
+
+impl core::fmt::Display for Limit {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(match self {
+            Limit::Sections => "number of sections",
+            Limit::VecLen => "vector length",
+            Limit::Locals => "number of locals",
+            Limit::Instructions => "number of instructions",
+            Limit::NestingDepth => "block nesting depth",
+            Limit::AllocatedBytes => "allocated bytes",
+        })
+    }
+}
+
+/// Caps on resources used while decoding untrusted input.
+///
+/// All limits are unlimited by default.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct DecodeLimits {
+    /// Maximum number of sections in a module.
+    pub max_sections: usize,
+    /// Maximum number of items in any vector.
+    pub max_vec_len: usize,
+    /// Maximum number of locals declared by a function body.
+    pub max_locals: usize,
+    /// Maximum number of instructions in a single expression.
+    pub max_instructions: usize,
+    /// Maximum nesting depth of blocks in an expression.
+    pub max_nesting_depth: usize,
+    /// Maximum total number of bytes allocated for decoded vectors and
+    /// raw contents.
+    pub max_allocated_bytes: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_sections: usize::MAX,
+            max_vec_len: usize::MAX,
+            max_locals: usize::MAX,
+            max_instructions: usize::MAX,
+            max_nesting_depth: usize::MAX,
+            max_allocated_bytes: usize::MAX,
+        }
+    }
+}
+
+impl DecodeLimits {
+    fn get(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Sections => self.max_sections,
+            Limit::VecLen => self.max_vec_len,
+            Limit::Locals => self.max_locals,
+            Limit::Instructions => self.max_instructions,
+            Limit::NestingDepth => self.max_nesting_depth,
+            Limit::AllocatedBytes => self.max_allocated_bytes,
+        }
+    }
+}
+
+/// Proposal that can be disabled at runtime via `Features`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Feature {
+    TailCall,
+    Simd,
+    RelaxedSimd,
+    Threads,
+    Exceptions,
+    Memory64,
+    FunctionReferences,
+    Gc,
+    ComponentModel,
+}
+
+impl core::fmt::Display for Feature {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        // Use the same names as the corresponding cargo features.
+        f.write_str(match self {
+            Feature::TailCall => "tail-call",
+            Feature::Simd => "simd",
+            Feature::RelaxedSimd => "relaxed-simd",
+            Feature::Threads => "threads",
+            Feature::Exceptions => "exceptions",
+            Feature::Memory64 => "memory64",
+            Feature::FunctionReferences => "function-references",
+            Feature::Gc => "gc",
+            Feature::ComponentModel => "component-model",
+        })
+    }
+}
+
+/// Proposals accepted while decoding.
+///
+/// Support for all proposals is always compiled in, and all of them are
+/// enabled by default. Pick the ones to accept per decode via
+/// [`DecodeOptions::features`]. Encodings of a disabled proposal fail to
+/// decode with [`DecodeErrorKind::FeatureNotEnabled`], whose path names the
+/// rejected instruction, type or section.
+///
+/// Cargo features named after proposals are only kept for compatibility,
+/// and don't change what's compiled in.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Features {
+    pub tail_call: bool,
+    pub simd: bool,
+    pub relaxed_simd: bool,
+    pub threads: bool,
+    pub exceptions: bool,
+    pub memory64: bool,
+    pub function_references: bool,
+    pub gc: bool,
+    pub component_model: bool,
+}
+
+impl Default for Features {
+    fn default() -> Self {
+        Features {
+            tail_call: true,
+            simd: true,
+            relaxed_simd: true,
+            threads: true,
+            exceptions: true,
+            memory64: true,
+            function_references: true,
+            gc: true,
+            component_model: true,
+        }
+    }
+}
+
+impl Features {
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::TailCall => self.tail_call,
+            Feature::Simd => self.simd,
+            Feature::RelaxedSimd => self.relaxed_simd,
+            Feature::Threads => self.threads,
+            Feature::Exceptions => self.exceptions,
+            Feature::Memory64 => self.memory64,
+            Feature::FunctionReferences => self.function_references,
+            Feature::Gc => self.gc,
+            Feature::ComponentModel => self.component_model,
+        }
+    }
+}
+
+/// Options for [`Module::decode_with_options`](crate::Module::decode_with_options).
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct DecodeOptions {
+    pub limits: DecodeLimits,
+    pub features: Features,
+    /// Keep the original bytes of sections and function bodies, along with
+    /// widths of their size prefixes, so that re-encoding reproduces the
+    /// input exactly (including non-canonical LEB128s) unless the contents
+    /// were modified.
+    ///
+    /// Modified contents are still encoded in the canonical form. This makes
+    /// encoding slower, as mutably accessed contents, e.g. via
+    /// [`Visit::visit_mut`](crate::visit::Visit::visit_mut), have to be
+    /// compared against the original ones.
+    pub preserve_encoding: bool,
+}
+
+#[derive(Debug)]
+struct ContextInner {
+    options: DecodeOptions,
+    allocated_bytes: AtomicUsize,
+}
+
+/// Options of a decoding in progress, passed to decoders by the reader via
+/// [`Read::decode_context`].
+///
+/// Clones share the same allocation budget. Lazily decoded values keep a
+/// clone, so that the same options, and the same budget, apply whenever
+/// and on whichever thread their contents are decoded.
+#[derive(Debug, Clone)]
+pub struct DecodeContext(Arc<ContextInner>);
+
+impl DecodeContext {
+    pub fn new(options: &DecodeOptions) -> Self {
+        DecodeContext(Arc::new(ContextInner {
+            options: options.clone(),
+            allocated_bytes: AtomicUsize::new(0),
+        }))
+    }
+
+    pub fn options(&self) -> &DecodeOptions {
+        &self.0.options
+    }
+
+    pub(crate) fn check_limit(&self, limit: Limit, value: usize) -> Result<(), DecodeError> {
+        let max = self.0.options.limits.get(limit);
+        if value > max {
+            return Err(DecodeErrorKind::LimitExceeded { limit, max }.into());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn require_feature(&self, feature: Feature) -> Result<(), DecodeError> {
+        if !self.0.options.features.is_enabled(feature) {
+            return Err(DecodeErrorKind::FeatureNotEnabled(feature).into());
+        }
+        Ok(())
+    }
+
+    /// Number of bytes that can still be allocated before exceeding
+    /// [`DecodeLimits::max_allocated_bytes`].
+    fn remaining_allocation(&self) -> usize {
+        let allocated = self.0.allocated_bytes.load(Ordering::Relaxed);
+        self.0
+            .options
+            .limits
+            .max_allocated_bytes
+            .saturating_sub(allocated)
+    }
+
+    /// Accounts `bytes` against [`DecodeLimits::max_allocated_bytes`].
+    pub(crate) fn track_allocation(&self, bytes: usize) -> Result<(), DecodeError> {
+        let allocated = self
+            .0
+            .allocated_bytes
+            .fetch_add(bytes, Ordering::Relaxed)
+            .saturating_add(bytes);
+        let max = self.0.options.limits.max_allocated_bytes;
+        if allocated > max {
+            return Err(DecodeErrorKind::LimitExceeded {
+                limit: Limit::AllocatedBytes,
+                max,
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Returns the value of `limit` for the decoding done by `r`.
+pub(crate) fn get_limit(r: &impl Read, limit: Limit) -> usize {
+    r.decode_context()
+        .map_or(usize::MAX, |context| context.options().limits.get(limit))
+}
+
+pub(crate) fn check_limit(r: &impl Read, limit: Limit, value: usize) -> Result<(), DecodeError> {
+    match r.decode_context() {
+        Some(context) => context.check_limit(limit, value),
+        None => Ok(()),
+    }
+}
+
+/// Fails if `feature` was disabled for the decoding done by `r` via
+/// `DecodeOptions::features`.
+pub fn require_feature(r: &impl Read, feature: Feature) -> Result<(), DecodeError> {
+    match r.decode_context() {
+        Some(context) => context.require_feature(feature),
+        None => Ok(()),
+    }
+}
+
+pub(crate) fn preserve_encoding(r: &impl Read) -> bool {
+    r.decode_context()
+        .is_some_and(|context| context.options().preserve_encoding)
+}
+
+/// Number of bytes that can still be allocated by the decoding done by `r`
+/// before exceeding [`DecodeLimits::max_allocated_bytes`].
+pub(crate) fn remaining_allocation(r: &impl Read) -> usize {
+    r.decode_context()
+        .map_or(usize::MAX, DecodeContext::remaining_allocation)
+}
+
+/// Accounts `bytes` against [`DecodeLimits::max_allocated_bytes`].
+///
+/// Must be called before allocating, so that bogus sizes in the input fail
+/// without allocating anything.
+pub(crate) fn track_allocation(r: &impl Read, bytes: usize) -> Result<(), DecodeError> {
+    match r.decode_context() {
+        Some(context) => context.track_allocation(bytes),
+        None => Ok(()),
+    }
+}
+
 pub trait Encode {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()>;
+    fn encode(&self, w: &mut impl Write) -> Result<(), EncodeError>;
+
+    /// Number of bytes that [`Encode::encode`] would write.
+    ///
+    /// The default implementation encodes into a writer that discards
+    /// the output.
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        let mut counter = ByteCounter::default();
+        self.encode(&mut counter)?;
+        Ok(counter.len)
+    }
 }
 
 pub trait Decode: Sized {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError>;
+    fn decode(r: &mut impl Read) -> Result<Self, DecodeError>;
 }
 
 macro_rules! encode_decode_as {
@@ -113,7 +979,7 @@ macro_rules! encode_decode_as {
     } $(, |$other:pat| $other_handler:expr)?) => {
         impl crate::io::Encode for $ty {
             #[allow(unused_parens)]
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), crate::io::EncodeError> {
                 match *self {
                     $($lhs => $rhs,)*
                 }.encode(w)
@@ -122,7 +988,7 @@ macro_rules! encode_decode_as {
 
         impl crate::io::Decode for $ty {
             #[allow(unused_parens)]
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, crate::io::DecodeError> {
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, crate::io::DecodeError> {
                 Ok(match crate::io::Decode::decode(r)? {
                     $($rhs => $lhs,)*
                     $($other => return $other_handler)?
@@ -138,12 +1004,12 @@ pub trait DecodeWithDiscriminant: Decode {
 
     fn maybe_decode_with_discriminant(
         discriminant: Self::Discriminant,
-        r: &mut impl std::io::Read,
+        r: &mut impl crate::io::Read,
     ) -> Result<Option<Self>, DecodeError>;
 
     fn decode_with_discriminant(
         discriminant: Self::Discriminant,
-        r: &mut impl std::io::Read,
+        r: &mut impl crate::io::Read,
     ) -> Result<Self, DecodeError> {
         Self::maybe_decode_with_discriminant(discriminant, r)?.ok_or_else(|| {
             DecodeErrorKind::UnsupportedDiscriminant {
@@ -154,7 +1020,7 @@ pub trait DecodeWithDiscriminant: Decode {
         })
     }
 
-    fn decode_without_discriminant(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode_without_discriminant(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         Self::decode_with_discriminant(Self::Discriminant::decode(r)?, r)
     }
 }
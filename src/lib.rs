@@ -12,6 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! A self-generating WebAssembly parser and serializer.
+//!
+//! # `no_std` support
+//!
+//! The crate only needs `alloc` when built without the default `std`
+//! feature. Decoding options, such as `io::DecodeLimits` and
+//! `io::Features`, work the same way in both builds. The `arbitrary`,
+//! `rayon` and `async` features are only available with `std`, and enable
+//! it themselves.
+
+#![no_std]
 #![cfg_attr(feature = "nightly", feature(arbitrary_enum_discriminant, never_type))]
 #![warn(clippy::all, clippy::pedantic)]
 #![allow(
@@ -21,6 +32,12 @@
     clippy::module_name_repetitions
 )]
 
+extern crate alloc;
+// Lets derived implementations refer to `::wasmbin` within this crate too.
+extern crate self as wasmbin;
+#[cfg(feature = "std")]
+extern crate std;
+
 use wasmbin_derive::wasmbin_discriminants;
 
 #[macro_use]
@@ -28,11 +45,20 @@ pub mod io;
 #[macro_use]
 pub mod visit;
 
+#[cfg(feature = "async")]
+mod async_io;
+pub mod borrowed;
 pub mod builtins;
+pub mod component;
 pub mod indices;
 pub mod instructions;
 pub mod module;
+pub mod offsets;
+#[cfg(feature = "rayon")]
+mod parallel;
 pub mod sections;
+pub mod streaming;
 pub mod types;
 
+pub use component::{decode_any, decode_any_with_options, Component};
 pub use module::Module;
@@ -13,24 +13,36 @@
 // limitations under the License.
 
 use crate::builtins::Blob;
-use crate::io::{Decode, DecodeError, DecodeErrorKind, Encode, Wasmbin};
+use crate::io::{
+    Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeOptions, Encode, EncodeError,
+    PathItem, PositionReader, Read, SliceReader, Wasmbin,
+};
+use crate::offsets::ModuleOffsets;
 use crate::sections::{Section, StdPayload};
 use crate::visit::Visit;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
-use std::cmp::Ordering;
+use core::cmp::Ordering;
 
-const MAGIC_AND_VERSION: [u8; 8] = [b'\0', b'a', b's', b'm', 0x01, 0x00, 0x00, 0x00];
+pub(crate) const MAGIC_AND_VERSION: [u8; 8] = [b'\0', b'a', b's', b'm', 0x01, 0x00, 0x00, 0x00];
 
-#[derive(Debug, Default, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct MagicAndVersion;
 
 encode_decode_as!(MagicAndVersion, {
     MagicAndVersion <=> MAGIC_AND_VERSION,
 }, |actual| {
-    Err(DecodeErrorKind::InvalidMagic { actual }.into())
+    Err(DecodeErrorKind::InvalidMagic {
+        expected: MAGIC_AND_VERSION,
+        actual,
+    }
+    .into())
 });
 
-#[derive(Wasmbin, Debug, Default, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Module {
     #[doc(hidden)]
     pub magic_and_version: MagicAndVersion,
@@ -38,11 +50,50 @@ pub struct Module {
 }
 
 impl Module {
-    pub fn decode_from(mut r: impl std::io::Read) -> Result<Module, DecodeError> {
-        Self::decode(&mut r)
+    pub fn decode_from(r: impl crate::io::Read) -> Result<Module, DecodeError> {
+        Self::decode_with_context(r, None)
+    }
+
+    /// Like [`Module::decode_from`], but reads straight from in-memory
+    /// input instead of going through [`Read`], which is faster.
+    pub fn decode_from_slice(input: &[u8]) -> Result<Module, DecodeError> {
+        let mut r = SliceReader::new(input, Some(0), None);
+        Self::decode(&mut r).map_err(|err| err.at_position(r.position()))
+    }
+
+    pub(crate) fn decode_with_context(
+        r: impl crate::io::Read,
+        context: Option<DecodeContext>,
+    ) -> Result<Module, DecodeError> {
+        let mut r = PositionReader::new(r, context);
+        Self::decode(&mut r).map_err(|err| err.at_offset(r.pos))
+    }
+
+    /// Like [`Module::decode_from`], but applies the given options.
+    ///
+    /// Options also apply to lazily decoded contents, such as function
+    /// bodies, whenever and on whichever thread they're decoded later, and
+    /// all of them share the same [`max_allocated_bytes`](crate::io::DecodeLimits::max_allocated_bytes)
+    /// budget.
+    pub fn decode_with_options(
+        r: impl crate::io::Read,
+        options: &DecodeOptions,
+    ) -> Result<Module, DecodeError> {
+        Self::decode_with_context(r, Some(DecodeContext::new(options)))
     }
 
-    pub fn encode_into<W: std::io::Write>(&self, mut w: W) -> std::io::Result<W> {
+    /// Like [`Module::decode_from`], but also records input byte ranges of
+    /// sections, function bodies and their instructions.
+    ///
+    /// Function bodies are decoded eagerly in this mode.
+    pub fn decode_with_offsets(
+        r: impl crate::io::Read,
+    ) -> Result<(Module, ModuleOffsets), DecodeError> {
+        crate::offsets::decode_with_offsets(r)
+            .map_err(|err| err.in_path(PathItem::Variant("Module")))
+    }
+
+    pub fn encode_into<W: crate::io::Write>(&self, mut w: W) -> Result<W, EncodeError> {
         self.encode(&mut w)?;
         Ok(w)
     }
@@ -79,6 +130,118 @@ impl Module {
         }
         unsafe { self.sections.get_unchecked_mut(index) }
             .try_as_mut()
-            .unwrap_or_else(|| unsafe { std::hint::unreachable_unchecked() })
+            .unwrap_or_else(|| unsafe { core::hint::unreachable_unchecked() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::Lazy;
+    use crate::indices::{FuncId, TypeId};
+    use crate::instructions::Instruction;
+    use crate::sections::{
+        payload, CustomSection, Export, ExportDesc, FuncBody, Import, ImportDesc, ImportPath,
+        Locals, ProducerField, ProducerVersionedName,
+    };
+    use crate::types::ValueType;
+    use alloc::borrow::ToOwned;
+    use alloc::string::String;
+    use alloc::vec;
+
+    fn assert_encoded_len(value: &impl Encode) {
+        let mut encoded = Vec::new();
+        value.encode(&mut encoded).unwrap();
+        assert_eq!(value.encoded_len().unwrap(), encoded.len());
+    }
+
+    fn module() -> Module {
+        // Enough locals and instructions for the body size to take two bytes.
+        let expr = (0..100)
+            .map(Instruction::I32Const)
+            .chain((0..100).map(|_| Instruction::Drop))
+            .collect();
+        Module {
+            sections: vec![
+                Section::Import(Blob::from(vec![Import {
+                    path: ImportPath {
+                        module: "env".to_owned(),
+                        name: "f".to_owned(),
+                    },
+                    desc: ImportDesc::Func(TypeId::from(0)),
+                }])),
+                Section::Function(Blob::from(vec![TypeId::from(0)])),
+                Section::Export(Blob::from(vec![Export {
+                    name: "a".repeat(200),
+                    desc: ExportDesc::Func(FuncId::from(0)),
+                }])),
+                Section::Code(Blob::from(vec![Blob::from(FuncBody {
+                    locals: vec![Locals {
+                        repeat: 300,
+                        ty: ValueType::F32,
+                    }],
+                    expr,
+                })])),
+                Section::Custom(Blob::from(CustomSection::Producers(Lazy::from(vec![
+                    ProducerField {
+                        name: "language".to_owned(),
+                        values: vec![ProducerVersionedName {
+                            name: "Rust".to_owned(),
+                            version: String::new(),
+                        }],
+                    },
+                ])))),
+            ],
+            ..Module::default()
+        }
+    }
+
+    #[test]
+    fn encoded_len_matches_constructed_module() {
+        let module = module();
+        assert_encoded_len(&module);
+        for section in &module.sections {
+            assert_encoded_len(section);
+        }
+    }
+
+    #[test]
+    fn encoded_len_matches_decoded_module() {
+        let input = module().encode_into(Vec::new()).unwrap();
+        let mut module = Module::decode_from_slice(&input).unwrap();
+        // Raw contents are written back as they were read.
+        assert_eq!(module.encoded_len().unwrap(), input.len());
+
+        // Modified contents are encoded again.
+        module
+            .find_std_section_mut::<payload::Code>()
+            .unwrap()
+            .try_contents_mut()
+            .unwrap()[0]
+            .try_contents_mut()
+            .unwrap()
+            .expr
+            .push(Instruction::Nop);
+        assert_encoded_len(&module);
+        assert_eq!(module.encoded_len().unwrap(), input.len() + 1);
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn encoded_len_matches_arbitrary_modules() {
+        use arbitrary::Unstructured;
+
+        let seed: Vec<u8> = (0..65536_u32)
+            .map(|i| i.wrapping_mul(2_654_435_761).to_be_bytes()[1])
+            .collect();
+        let mut u = Unstructured::new(&seed);
+        while !u.is_empty() {
+            let module = Module::arbitrary(&mut u).unwrap();
+            let mut encoded = Vec::new();
+            match module.encode(&mut encoded) {
+                Ok(()) => assert_eq!(module.encoded_len().unwrap(), encoded.len()),
+                Err(_) => assert!(module.encoded_len().is_err()),
+            }
+        }
     }
 }
@@ -0,0 +1,196 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::borrowed::DecodeBorrowed;
+use crate::builtins::{Blob, RawBlob};
+use crate::io::{Decode, DecodeError, DecodeErrorKind, PathItem, PositionReader, Read};
+use crate::module::MagicAndVersion;
+use crate::sections::{FuncBody, Kind, Section, SectionOrderTracker};
+use crate::Module;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct SectionOffsets {
+    /// Range of the whole section, including its id and size.
+    pub range: Range<usize>,
+    /// Range of the section contents.
+    pub contents: Range<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct FuncBodyOffsets {
+    /// Range of the function body contents, excluding its size.
+    pub range: Range<usize>,
+    /// Range of each instruction in [`FuncBody::expr`].
+    pub instructions: Vec<Range<usize>>,
+}
+
+/// Byte ranges of decoded items in the input, as returned by
+/// [`Module::decode_with_offsets`].
+#[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
+pub struct ModuleOffsets {
+    /// Offsets of each of [`Module::sections`].
+    pub sections: Vec<SectionOffsets>,
+    /// Offsets of each function body in the Code section.
+    pub func_bodies: Vec<FuncBodyOffsets>,
+}
+
+fn decode_code(
+    raw: &[u8],
+    base: usize,
+    offsets: &mut Vec<FuncBodyOffsets>,
+) -> Result<Vec<Blob<FuncBody>>, DecodeError> {
+    let mut r = raw;
+    let count = usize::decode(&mut r)?;
+    (0..count)
+        .map(|i| {
+            (|| {
+                let bytes = <&[u8]>::decode_borrowed(&mut r, None)?;
+                let start = base + (raw.len() - r.len() - bytes.len());
+                let (body, starts) = FuncBody::decode_with_offsets(bytes)?;
+                offsets.push(FuncBodyOffsets {
+                    range: start..start + bytes.len(),
+                    instructions: starts
+                        .windows(2)
+                        .map(|w| start + w[0]..start + w[1])
+                        .collect(),
+                });
+                Ok(Blob::from_raw_parsed(bytes.to_vec(), body))
+            })()
+            .map_err(move |err: DecodeError| err.in_path(PathItem::Index(i)))
+        })
+        .collect::<Result<_, _>>()
+        .and_then(|bodies| match r.is_empty() {
+            true => Ok(bodies),
+            false => Err(DecodeErrorKind::UnrecognizedData.into()),
+        })
+}
+
+pub(crate) fn decode_with_offsets(
+    r: impl crate::io::Read,
+) -> Result<(Module, ModuleOffsets), DecodeError> {
+    let mut r = PositionReader::new(r, None);
+    decode_sections_with_offsets(&mut r).map_err(|err| err.at_offset(r.pos))
+}
+
+fn decode_sections_with_offsets(
+    r: &mut PositionReader<impl crate::io::Read>,
+) -> Result<(Module, ModuleOffsets), DecodeError> {
+    let magic_and_version = MagicAndVersion::decode(r)
+        .map_err(|err| err.in_path(PathItem::Name("magic_and_version")))?;
+    let mut sections = Vec::new();
+    let mut offsets = ModuleOffsets::default();
+    let mut section_order_tracker = SectionOrderTracker::default();
+    let mut start = r.pos;
+    while let Some(disc) = Option::<u8>::decode(r)? {
+        let i = sections.len();
+        (|| -> Result<(), DecodeError> {
+            let kind =
+                Kind::try_from(disc).map_err(|_| DecodeErrorKind::UnsupportedDiscriminant {
+                    ty: "Section",
+                    discriminant: disc.into(),
+                })?;
+            kind.require_feature(r.decode_context())?;
+            section_order_tracker.try_add_kind(kind)?;
+            let raw = RawBlob::<Vec<u8>>::decode(r)?.contents;
+            let contents = r.pos - raw.len()..r.pos;
+            sections.push(match kind {
+                Kind::Code => {
+                    let bodies = decode_code(&raw, contents.start, &mut offsets.func_bodies)
+                        .map_err(|err| err.in_path(PathItem::Variant("Section::Code")))?;
+                    Section::Code(Blob::from_raw_parsed(raw, bodies))
+                }
+                _ => Section::from_raw(kind, raw, Some(contents.start), r.decode_context()),
+            });
+            offsets.sections.push(SectionOffsets {
+                range: start..r.pos,
+                contents,
+            });
+            Ok(())
+        })()
+        .map_err(move |err| {
+            err.in_path(PathItem::Index(i))
+                .in_path(PathItem::Name("sections"))
+        })?;
+        start = r.pos;
+    }
+    Ok((
+        Module {
+            magic_and_version,
+            sections,
+        },
+        offsets,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtins::Blob;
+    use crate::indices::TypeId;
+    use crate::instructions::Instruction;
+    use crate::sections::{FuncBody, Section};
+    use crate::Module;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn body(expr: Vec<Instruction>) -> Blob<FuncBody> {
+        Blob::from(FuncBody {
+            locals: vec![],
+            expr,
+        })
+    }
+
+    #[test]
+    fn offsets_cover_sections_bodies_and_instructions() {
+        let input = Module {
+            sections: vec![
+                Section::Function(Blob::from(vec![TypeId::from(0), TypeId::from(0)])),
+                Section::Code(Blob::from(vec![
+                    body(vec![Instruction::Nop]),
+                    body(vec![
+                        Instruction::Nop,
+                        Instruction::I32Const(0),
+                        Instruction::Drop,
+                    ]),
+                ])),
+            ],
+            ..Module::default()
+        }
+        .encode_into(Vec::new())
+        .unwrap();
+        let (module, offsets) = Module::decode_with_offsets(input.as_slice()).unwrap();
+        assert_eq!(module, Module::decode_from_slice(&input).unwrap());
+
+        // Function section: id, size, count and two type indices.
+        assert_eq!(offsets.sections[0].range, 8..13);
+        assert_eq!(offsets.sections[0].contents, 10..13);
+        // Code section: id, size, count and two sized bodies.
+        assert_eq!(offsets.sections[1].range, 13..27);
+        assert_eq!(offsets.sections[1].contents, 15..27);
+        assert_eq!(offsets.sections.len(), module.sections.len());
+
+        // Each body range excludes its size but includes the locals.
+        assert_eq!(offsets.func_bodies.len(), 2);
+        assert_eq!(offsets.func_bodies[0].range, 17..20);
+        assert_eq!(offsets.func_bodies[0].instructions, vec![18..19]);
+        assert_eq!(offsets.func_bodies[1].range, 21..27);
+        assert_eq!(
+            offsets.func_bodies[1].instructions,
+            [22..23, 23..25, 25..26]
+        );
+        assert_eq!(input[26], 0x0B);
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::builtins::Blob;
+use crate::io::{DecodeError, PathItem};
+use crate::sections::{payload, FuncBody};
+use crate::visit::{NeverError, Visit, VisitError, VisitResult};
+use crate::Module;
+use rayon::prelude::*;
+
+// Paths mirror the ones produced by sequential `Visit` on the whole module.
+//
+// Options of `Module::decode_with_options` don't need to be passed to the
+// workers explicitly: each lazy body carries the context it was decoded
+// with, so they apply on whichever thread the body is decoded.
+
+fn in_blob_path<E>(err: VisitError<E>) -> VisitError<E> {
+    err.in_path(PathItem::Name("contents"))
+        .in_path(PathItem::Variant("Blob"))
+}
+
+fn in_code_path<E>(err: VisitError<E>, section_index: usize) -> VisitError<E> {
+    in_blob_path(err)
+        .in_path(PathItem::Variant("Section::Code"))
+        .in_path(PathItem::Index(section_index))
+        .in_path(PathItem::Name("sections"))
+        .in_path(PathItem::Variant("Module"))
+}
+
+impl Module {
+    fn code_section_mut(&mut self) -> Option<(usize, &mut Blob<payload::Code>)> {
+        self.sections
+            .iter_mut()
+            .enumerate()
+            .find_map(|(i, section)| section.try_as_mut::<payload::Code>().map(|code| (i, code)))
+    }
+
+    /// Decodes all lazy function bodies in the Code section in parallel.
+    ///
+    /// Bodies of a module decoded with [`Module::decode_with_options`] are
+    /// decoded with the same options, sharing the same allocation budget.
+    ///
+    /// On failure, returns the error for the first invalid function body.
+    pub fn par_decode_func_bodies(&self) -> Result<(), DecodeError> {
+        let Some((section_index, code)) = self
+            .sections
+            .iter()
+            .enumerate()
+            .find_map(|(i, section)| section.try_as::<payload::Code>().map(|code| (i, code)))
+        else {
+            return Ok(());
+        };
+        let bodies = code
+            .try_contents()
+            .map_err(|err| in_code_path(VisitError::LazyDecode(err), section_index))?;
+        bodies
+            .par_iter()
+            .enumerate()
+            .map(|(i, body)| {
+                body.try_contents().map_err(|err| {
+                    in_blob_path(VisitError::LazyDecode(err)).in_path(PathItem::Index(i))
+                })
+            })
+            .find_map_first(Result::err)
+            .map_or(Ok(()), |err| Err(in_code_path(err, section_index)))
+            .map_err(|err: VisitError<NeverError>| err.into())
+    }
+
+    /// Like [`Visit::visit_mut`], but only visits function bodies in the
+    /// Code section, decoding and visiting them in parallel.
+    ///
+    /// On failure, returns the error for the first function body that failed.
+    pub fn par_visit_func_bodies_mut<T, R, F>(&mut self, f: F) -> Result<(), VisitError<R::Error>>
+    where
+        T: 'static,
+        R: VisitResult,
+        R::Error: Send,
+        F: Fn(&mut T) -> R + Sync,
+    {
+        let Some((section_index, code)) = self.code_section_mut() else {
+            return Ok(());
+        };
+        let bodies = code
+            .try_contents_mut()
+            .map_err(|err| in_code_path(VisitError::LazyDecode(err), section_index))?;
+        bodies
+            .par_iter_mut()
+            .enumerate()
+            .map(|(i, body): (usize, &mut Blob<FuncBody>)| {
+                body.visit_mut(&f)
+                    .map_err(|err| err.in_path(PathItem::Index(i)))
+            })
+            .find_map_first(Result::err)
+            .map_or(Ok(()), |err| Err(in_code_path(err, section_index)))
+    }
+}
@@ -12,28 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod code_metadata;
+pub mod dylink;
+pub mod linking;
+use alloc::string::String;
+use alloc::vec::Vec;
+use code_metadata::{BranchHint, CodeMetadataFunc};
+use dylink::DylinkSubSection;
+use linking::{Linking, Reloc};
+
 use crate::builtins::Lazy;
 use crate::builtins::WasmbinCountable;
 use crate::builtins::{Blob, RawBlob};
-use crate::indices::{FuncId, GlobalId, LocalId, MemId, TableId, TypeId};
-use crate::instructions::Expression;
+use crate::indices::{
+    DataId, ElemId, FieldId, FuncId, GlobalId, LabelId, LocalId, MemId, TableId, TagId, TypeId,
+};
+use crate::instructions::{decode_with_callback, Expression};
+use crate::io::Feature;
 use crate::io::{
-    Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin,
+    check_limit, Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeWithDiscriminant,
+    Encode, EncodeError, Limit, PathItem, Wasmbin,
 };
-use crate::types::{FuncType, GlobalType, MemType, RefType, TableType, ValueType};
+use crate::types::{GlobalType, MemType, RecGroup, RefType, TableType, ValueType};
 use crate::visit::Visit;
 use crate::wasmbin_discriminants;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
+use core::convert::TryFrom;
 use custom_debug::Debug as CustomDebug;
-use std::convert::TryFrom;
 use thiserror::Error;
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct ModuleNameSubSection {
     pub name: String,
 }
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct NameAssoc<I, V> {
     pub index: I,
     pub value: V,
@@ -41,22 +57,72 @@ pub struct NameAssoc<I, V> {
 
 impl<I, V> WasmbinCountable for NameAssoc<I, V> {}
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct NameMap<I, V> {
     pub items: Vec<NameAssoc<I, V>>,
 }
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum NameSubSection {
     Module(Blob<String>) = 0,
     Func(Blob<NameMap<FuncId, String>>) = 1,
     Local(Blob<NameMap<FuncId, NameMap<LocalId, String>>>) = 2,
+    Label(Blob<NameMap<FuncId, NameMap<LabelId, String>>>) = 3,
+    Type(Blob<NameMap<TypeId, String>>) = 4,
+    Table(Blob<NameMap<TableId, String>>) = 5,
+    Memory(Blob<NameMap<MemId, String>>) = 6,
+    Global(Blob<NameMap<GlobalId, String>>) = 7,
+    Elem(Blob<NameMap<ElemId, String>>) = 8,
+    Data(Blob<NameMap<DataId, String>>) = 9,
+    Field(Blob<NameMap<TypeId, NameMap<FieldId, String>>>) = 10,
+    Tag(Blob<NameMap<TagId, String>>) = 11,
+    Unknown(UnknownNameSubSection),
+}
+
+/// Name subsection with an unrecognised id, preserved as raw bytes.
+#[derive(CustomDebug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct UnknownNameSubSection {
+    pub id: u8,
+
+    #[debug(with = "custom_debug::hexbuf_str")]
+    pub data: RawBlob,
+}
+
+impl Encode for UnknownNameSubSection {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        self.id.encode(w)?;
+        self.data.encode(w)
+    }
+}
+
+impl DecodeWithDiscriminant for UnknownNameSubSection {
+    const NAME: &'static str = "UnknownNameSubSection";
+    type Discriminant = u8;
+
+    fn maybe_decode_with_discriminant(
+        discriminant: u8,
+        r: &mut impl crate::io::Read,
+    ) -> Result<Option<Self>, DecodeError> {
+        Ok(Some(UnknownNameSubSection {
+            id: discriminant,
+            data: RawBlob::decode(r)?,
+        }))
+    }
+}
+
+impl Decode for UnknownNameSubSection {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        DecodeWithDiscriminant::decode_without_discriminant(r)
+    }
 }
 
 impl Encode for [NameSubSection] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         for sub in self {
             sub.encode(w)?;
         }
@@ -64,33 +130,71 @@ impl Encode for [NameSubSection] {
     }
 }
 
+/// Decodes subsections until the end of the containing custom section.
+pub(crate) fn decode_subsections<T: DecodeWithDiscriminant<Discriminant = u8>>(
+    r: &mut impl crate::io::Read,
+) -> Result<Vec<T>, DecodeError> {
+    let mut sub = Vec::new();
+    let mut start = r.position();
+    while let Some(disc) = Option::decode(r)? {
+        let i = sub.len();
+        sub.push(
+            T::decode_with_discriminant(disc, r)
+                .map_err(move |err| err.in_path(PathItem::Index(i)).at_position(start))?,
+        );
+        start = r.position();
+    }
+    Ok(sub)
+}
+
 impl Decode for Vec<NameSubSection> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
-        let mut sub = Vec::new();
-        while let Some(disc) = Option::decode(r)? {
-            let i = sub.len();
-            sub.push(
-                NameSubSection::decode_with_discriminant(disc, r)
-                    .map_err(move |err| err.in_path(PathItem::Index(i)))?,
-            );
-        }
-        Ok(sub)
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        decode_subsections(r)
     }
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct ProducerField {
     pub name: String,
     pub values: Vec<ProducerVersionedName>,
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct ProducerVersionedName {
     pub name: String,
     pub version: String,
 }
 
-#[derive(Wasmbin, CustomDebug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum TargetFeaturePrefix {
+    /// `+`
+    Used = 0x2B,
+    /// `-`
+    Disallowed = 0x2D,
+    /// `=`, no longer emitted by current toolchains.
+    Required = 0x3D,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct TargetFeature {
+    pub prefix: TargetFeaturePrefix,
+    pub name: String,
+}
+
+#[derive(Wasmbin, CustomDebug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct BuildId {
+    #[debug(with = "custom_debug::hexbuf_str")]
+    pub id: RawBlob,
+}
+
+#[derive(Wasmbin, CustomDebug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct RawCustomSection {
     pub name: String,
 
@@ -98,40 +202,141 @@ pub struct RawCustomSection {
     pub data: Vec<u8>,
 }
 
+/// Contents of custom sections that are recognised by a name prefix.
+pub trait CustomSectionPrefix {
+    /// Prefix of the names of such sections, e.g. `reloc.`.
+    const PREFIX: &'static str;
+}
+
+/// Custom section that's recognised by a name prefix, e.g. `reloc.CODE`.
+///
+/// The name is only accessible via methods, so that it always starts with
+/// the prefix.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct PrefixedCustomSection<T: Decode> {
+    name: String,
+    pub data: Lazy<T>,
+}
+
+impl<T: Decode> PrefixedCustomSection<T> {
+    /// Full name of the section, including the prefix.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T: Decode + CustomSectionPrefix> PrefixedCustomSection<T> {
+    /// Creates a section named with the prefix followed by `suffix`.
+    pub fn new(suffix: &str, data: Lazy<T>) -> Self {
+        let mut name = String::from(T::PREFIX);
+        name.push_str(suffix);
+        PrefixedCustomSection { name, data }
+    }
+
+    /// Part of the name after the prefix, e.g. `CODE` for `reloc.CODE`.
+    pub fn suffix(&self) -> &str {
+        &self.name[T::PREFIX.len()..]
+    }
+}
+
+impl<T: Decode + Encode> Encode for PrefixedCustomSection<T> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        self.name.encode(w)?;
+        self.data.encode(w)
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        Ok(self.name.encoded_len()? + self.data.encoded_len()?)
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a, T: Decode + CustomSectionPrefix + Arbitrary<'a>> Arbitrary<'a>
+    for PrefixedCustomSection<T>
+{
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self::new(u.arbitrary()?, u.arbitrary()?))
+    }
+}
+
 macro_rules! define_custom_sections {
-    ($($name:ident($ty:ty) = $disc:literal,)*) => {
-        #[derive(Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+    (
+        $($name:ident($ty:ty) = $disc:literal,)*
+        $(; $($prefixed_name:ident($prefixed_ty:ty) = $prefix:literal ..,)*)?
+    ) => {
+        #[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
         pub enum CustomSection {
             $($name(Lazy<$ty>),)*
+            $($($prefixed_name(PrefixedCustomSection<$prefixed_ty>),)*)?
             Other(RawCustomSection),
         }
 
+        $($(impl CustomSectionPrefix for $prefixed_ty {
+            const PREFIX: &'static str = $prefix;
+        })*)?
+
         impl CustomSection {
             pub fn name(&self) -> &str {
                 match self {
                     $(Self::$name(_) => $disc,)*
+                    $($(Self::$prefixed_name(section) => section.name(),)*)?
                     Self::Other(raw) => raw.name.as_str(),
                 }
             }
+
+            /// Whether sections with the given name are decoded as one of
+            /// the known kinds rather than `Other`.
+            fn is_known_name(name: &str) -> bool {
+                matches!(name, $($disc)|*) $($(|| name.starts_with($prefix))*)?
+            }
+        }
+
+        // Sections of other kinds can't have any of the known names, as
+        // they would be decoded as a different kind.
+        #[cfg(feature = "arbitrary")]
+        impl<'a> Arbitrary<'a> for CustomSection {
+            fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+                let kinds: &[fn(&mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self>] = &[
+                    $(|u| u.arbitrary().map(Self::$name),)*
+                    $($(|u| u.arbitrary().map(Self::$prefixed_name),)*)?
+                    |u| {
+                        let raw = RawCustomSection::arbitrary(u)?;
+                        if Self::is_known_name(&raw.name) {
+                            return Err(arbitrary::Error::IncorrectFormat);
+                        }
+                        Ok(Self::Other(raw))
+                    },
+                ];
+                u.choose(kinds)?(u)
+            }
         }
 
         impl Encode for CustomSection {
-            fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+            fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
                 match self {
                     $(CustomSection::$name(data) => {
                         $disc.encode(w)?;
                         data.encode(w)
                     })*
+                    $($(CustomSection::$prefixed_name(section) => section.encode(w),)*)?
                     CustomSection::Other(raw) => raw.encode(w)
                 }
             }
         }
 
         impl Decode for CustomSection {
-            fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+            fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
                 let raw = RawCustomSection::decode(r)?;
+                // Data spans the rest of the section.
+                let offset = r.position().map(|end| end - raw.data.len());
                 Ok(match raw.name.as_str() {
-                    $($disc => CustomSection::$name(Lazy::from_raw(raw.data)),)*
+                    $($disc => CustomSection::$name(Lazy::from_input(raw.data, offset, r.decode_context(), None)),)*
+                    $($(name if name.starts_with($prefix) => {
+                        CustomSection::$prefixed_name(PrefixedCustomSection {
+                            name: raw.name,
+                            data: Lazy::from_input(raw.data, offset, r.decode_context(), None),
+                        })
+                    })*)?
                     _ => CustomSection::Other(raw)
                 })
             }
@@ -146,60 +351,98 @@ define_custom_sections! {
     ExternalDebugInfo(String) = "external_debug_info",
     // https://github.com/WebAssembly/tool-conventions/blob/08bacbed/Debugging.md#source-maps
     SourceMappingUrl(String) = "sourceMappingURL",
+    // https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#target-features-section
+    TargetFeatures(Vec<TargetFeature>) = "target_features",
+    // https://github.com/WebAssembly/tool-conventions/blob/main/DynamicLinking.md
+    Dylink0(Vec<DylinkSubSection>) = "dylink.0",
+    // https://github.com/WebAssembly/tool-conventions/blob/main/BuildId.md
+    BuildId(BuildId) = "build_id",
+    // https://github.com/WebAssembly/branch-hinting/blob/main/proposals/branch-hinting/Overview.md
+    BranchHint(Vec<CodeMetadataFunc<BranchHint>>) = "metadata.code.branch_hint",
+    // https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#linking-metadata-section
+    Linking(Linking) = "linking",
+    ;
+    // https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md#relocation-sections
+    Reloc(Reloc) = "reloc."..,
+    // https://github.com/WebAssembly/tool-conventions/blob/main/CodeMetadata.md
+    CodeMetadata(Vec<CodeMetadataFunc<Vec<u8>>>) = "metadata.code."..,
 }
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum ImportDesc {
     Func(TypeId) = 0x00,
     Table(TableType) = 0x01,
     Mem(MemType) = 0x02,
     Global(GlobalType) = 0x03,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Tag(Tag) = 0x04,
 }
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct ImportPath {
     pub module: String,
     pub name: String,
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Import {
     pub path: ImportPath,
     pub desc: ImportDesc,
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+/// Exception tag.
+///
+/// The only attribute currently defined is "exception" (0x00), so it's
+/// used as the discriminant of the type.
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[wasmbin(discriminant = 0x00)]
+pub struct Tag {
+    pub ty: TypeId,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Global {
     pub ty: GlobalType,
     pub init: Expression,
 }
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum ExportDesc {
     Func(FuncId) = 0x00,
     Table(TableId) = 0x01,
     Mem(MemId) = 0x02,
     Global(GlobalId) = 0x03,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Tag(TagId) = 0x04,
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Export {
     pub name: String,
     pub desc: ExportDesc,
 }
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum ElemKind {
     FuncRef = 0x00,
 }
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum Element {
     ActiveWithFuncs {
@@ -240,22 +483,98 @@ pub enum Element {
     } = 0x07,
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Locals {
     pub repeat: u32,
     pub ty: ValueType,
 }
 
-#[derive(
-    Wasmbin, WasmbinCountable, Debug, Default, Arbitrary, PartialEq, Eq, Hash, Clone, Visit,
-)]
+#[derive(WasmbinCountable, Debug, Default, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct FuncBody {
     pub locals: Vec<Locals>,
     pub expr: Expression,
 }
 
+impl Encode for FuncBody {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        (|| -> Result<(), EncodeError> {
+            self.locals
+                .encode(w)
+                .map_err(|err| err.in_path(PathItem::Name("locals")))?;
+            self.expr
+                .encode(w)
+                .map_err(|err| err.in_path(PathItem::Name("expr")))
+        })()
+        .map_err(|err| err.in_path(PathItem::Variant("FuncBody")))
+    }
+
+    fn encoded_len(&self) -> Result<usize, EncodeError> {
+        (|| -> Result<usize, EncodeError> {
+            Ok(self
+                .locals
+                .encoded_len()
+                .map_err(|err| err.in_path(PathItem::Name("locals")))?
+                + self
+                    .expr
+                    .encoded_len()
+                    .map_err(|err| err.in_path(PathItem::Name("expr")))?)
+        })()
+        .map_err(|err| err.in_path(PathItem::Variant("FuncBody")))
+    }
+}
+
+impl Decode for FuncBody {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        Self::decode_with_callback(r, |_| {})
+    }
+}
+
+impl FuncBody {
+    // Implemented manually to enforce the limit on the total number of locals,
+    // which isn't known until all `Locals` are decoded.
+    fn decode_with_callback<R: crate::io::Read>(
+        r: &mut R,
+        before_instr: impl FnMut(&R),
+    ) -> Result<Self, DecodeError> {
+        (|| -> Result<Self, DecodeError> {
+            let locals = (|| -> Result<Vec<Locals>, DecodeError> {
+                let locals: Vec<Locals> = Vec::decode(r)?;
+                let count: u64 = locals.iter().map(|locals| u64::from(locals.repeat)).sum();
+                check_limit(
+                    r,
+                    Limit::Locals,
+                    usize::try_from(count).unwrap_or(usize::MAX),
+                )?;
+                Ok(locals)
+            })()
+            .map_err(|err| err.in_path(PathItem::Name("locals")))?;
+            let expr = decode_with_callback(r, before_instr)
+                .map_err(|err| err.in_path(PathItem::Name("expr")))?;
+            Ok(FuncBody { locals, expr })
+        })()
+        .map_err(|err| err.in_path(PathItem::Variant("FuncBody")))
+    }
+
+    /// Decodes function body contents along with offsets of each instruction
+    /// in `expr`, followed by the offset of the final `end`.
+    pub(crate) fn decode_with_offsets(bytes: &[u8]) -> Result<(Self, Vec<usize>), DecodeError> {
+        let mut r = bytes;
+        let mut offsets = Vec::new();
+        let body = Self::decode_with_callback(&mut r, |r: &&[u8]| {
+            offsets.push(bytes.len() - r.len());
+        })?;
+        if !r.is_empty() {
+            return Err(DecodeErrorKind::UnrecognizedData.into());
+        }
+        Ok((body, offsets))
+    }
+}
+
 #[wasmbin_discriminants]
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum DataInit {
     Active { offset: Expression } = 0x00,
@@ -263,7 +582,8 @@ pub enum DataInit {
     ActiveWithMemory { memory: MemId, offset: Expression } = 0x02,
 }
 
-#[derive(Wasmbin, WasmbinCountable, CustomDebug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, CustomDebug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Data {
     pub init: DataInit,
     #[debug(with = "custom_debug::hexbuf_str")]
@@ -275,22 +595,27 @@ pub trait Payload: Encode + Decode + Into<Section> {
 
     fn try_from_ref(section: &Section) -> Option<&Blob<Self>>;
     fn try_from_mut(section: &mut Section) -> Option<&mut Blob<Self>>;
+    // Gives back the section on mismatch, like `TryFrom` would.
+    #[allow(clippy::result_large_err)]
     fn try_from(section: Section) -> Result<Blob<Self>, Section>;
 }
 
 pub trait StdPayload: Payload {}
 
 macro_rules! define_sections {
-    ($($(# $attr:tt)? $name:ident($ty:ty) = $disc:literal,)*) => {
+    ($($(# $attr:tt)? $name:ident($ty:ty) = $disc:literal $(requires $feature:expr)?,)*) => {
         pub mod payload {
+            use alloc::vec::Vec;
+
             $($(# $attr)? pub type $name = $ty;)*
         }
 
         #[wasmbin_discriminants]
-        #[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+        #[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+        #[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
         #[repr(u8)]
         pub enum Section {
-            $($(# $attr)? $name(Blob<payload::$name>) = $disc,)*
+            $($(# $attr)? $(#[wasmbin(requires = $feature)])? $name(Blob<payload::$name>) = $disc,)*
         }
 
         #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
@@ -311,7 +636,7 @@ macro_rules! define_sections {
         }
 
         impl Ord for Kind {
-            fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
                 // Some new sections might have larger discriminants,
                 // but be ordered logically between those will smaller
                 // discriminants.
@@ -336,8 +661,32 @@ macro_rules! define_sections {
             }
         }
 
+        impl Kind {
+            /// Fails if sections of this kind belong to a proposal that is
+            /// disabled for a decoding with the given context.
+            ///
+            /// Only needed where contents are read without going through
+            /// [`Section`]'s own `Decode`.
+            pub(crate) fn require_feature(
+                self,
+                context: Option<&DecodeContext>,
+            ) -> Result<(), DecodeError> {
+                let Some(context) = context else {
+                    return Ok(());
+                };
+                $($(# $attr)? {
+                    $(if self == Kind::$name {
+                        context.require_feature($feature).map_err(|err| {
+                            err.in_path(PathItem::Variant(concat!("Section::", stringify!($name))))
+                        })?;
+                    })?
+                })*
+                Ok(())
+            }
+        }
+
         impl PartialOrd for Kind {
-            fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
                 Some(self.cmp(other))
             }
         }
@@ -395,6 +744,17 @@ macro_rules! define_sections {
             pub fn try_as_mut<T: Payload>(&mut self) -> Option<&mut Blob<T>> {
                 T::try_from_mut(self)
             }
+
+            pub(crate) fn from_raw(
+                kind: Kind,
+                raw: Vec<u8>,
+                offset: Option<usize>,
+                context: Option<&DecodeContext>,
+            ) -> Self {
+                match kind {
+                    $($(# $attr)? Kind::$name => Section::$name(Blob::from_input(raw, offset, context)),)*
+                }
+            }
         }
 
         define_sections!(@std $($(# $attr)? $name)*);
@@ -407,11 +767,12 @@ macro_rules! define_sections {
 
 define_sections! {
     Custom(super::CustomSection) = 0,
-    Type(Vec<super::FuncType>) = 1,
+    Type(Vec<super::RecGroup>) = 1,
     Import(Vec<super::Import>) = 2,
     Function(Vec<super::TypeId>) = 3,
     Table(Vec<super::TableType>) = 4,
     Memory(Vec<super::MemType>) = 5,
+    Tag(Vec<super::Tag>) = 13 requires Feature::Exceptions,
     Global(Vec<super::Global>) = 6,
     Export(Vec<super::Export>) = 7,
     Start(super::FuncId) = 8,
@@ -428,13 +789,8 @@ pub struct SectionOrderError {
     pub prev: Kind,
 }
 
-impl From<SectionOrderError> for std::io::Error {
-    fn from(err: SectionOrderError) -> Self {
-        Self::new(std::io::ErrorKind::InvalidData, err)
-    }
-}
-
-struct SectionOrderTracker {
+#[derive(Debug)]
+pub(crate) struct SectionOrderTracker {
     last_kind: Kind,
 }
 
@@ -448,7 +804,11 @@ impl Default for SectionOrderTracker {
 
 impl SectionOrderTracker {
     pub fn try_add(&mut self, section: &Section) -> Result<(), SectionOrderError> {
-        match section.kind() {
+        self.try_add_kind(section.kind())
+    }
+
+    pub fn try_add_kind(&mut self, kind: Kind) -> Result<(), SectionOrderError> {
+        match kind {
             Kind::Custom => {}
             kind if kind > self.last_kind => {
                 self.last_kind = kind;
@@ -465,30 +825,126 @@ impl SectionOrderTracker {
 }
 
 impl Encode for [Section] {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         let mut section_order_tracker = SectionOrderTracker::default();
-        for section in self {
-            section_order_tracker.try_add(section)?;
-            section.encode(w)?;
+        for (i, section) in self.iter().enumerate() {
+            section_order_tracker
+                .try_add(section)
+                .map_err(EncodeError::from)
+                .and_then(|()| section.encode(w))
+                .map_err(move |err| err.in_path(PathItem::Index(i)))?;
         }
         Ok(())
     }
 }
 
 impl Decode for Vec<Section> {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         let mut sections = Vec::new();
         let mut section_order_tracker = SectionOrderTracker::default();
+        let mut start = r.position();
         while let Some(disc) = Option::decode(r)? {
             let i = sections.len();
             (|| -> Result<(), DecodeError> {
+                check_limit(r, Limit::Sections, i + 1)?;
                 let section = Section::decode_with_discriminant(disc, r)?;
                 section_order_tracker.try_add(&section)?;
                 sections.push(section);
                 Ok(())
             })()
-            .map_err(move |err| err.in_path(PathItem::Index(i)))?;
+            .map_err(move |err| err.in_path(PathItem::Index(i)).at_position(start))?;
+            start = r.position();
         }
         Ok(sections)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::SliceReader;
+    use alloc::borrow::ToOwned;
+    use alloc::vec;
+
+    /// Checks that the section encodes to the expected bytes and decodes
+    /// back from them.
+    fn assert_encoding(section: &CustomSection, expected: &[u8]) {
+        let mut encoded = Vec::new();
+        section.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = CustomSection::decode(&mut SliceReader::new(expected, None, None)).unwrap();
+        assert_eq!(&decoded, section);
+    }
+
+    #[test]
+    fn target_features_encoding() {
+        let section = CustomSection::TargetFeatures(Lazy::from(vec![
+            TargetFeature {
+                prefix: TargetFeaturePrefix::Used,
+                name: "simd128".to_owned(),
+            },
+            TargetFeature {
+                prefix: TargetFeaturePrefix::Disallowed,
+                name: "atomics".to_owned(),
+            },
+            TargetFeature {
+                prefix: TargetFeaturePrefix::Required,
+                name: "bulk-memory".to_owned(),
+            },
+        ]));
+        let mut expected = b"\x0ftarget_features\x03".to_vec();
+        expected.extend_from_slice(b"+\x07simd128-\x07atomics=\x0bbulk-memory");
+        assert_encoding(&section, &expected);
+    }
+
+    #[test]
+    fn build_id_encoding() {
+        let section = CustomSection::BuildId(Lazy::from(BuildId {
+            id: RawBlob {
+                contents: vec![0xDE, 0xAD, 0xBE, 0xEF],
+            },
+        }));
+        assert_encoding(&section, b"\x08build_id\x04\xDE\xAD\xBE\xEF");
+    }
+
+    fn empty_names<I: Decode, V: Decode>() -> Blob<NameMap<I, V>> {
+        Blob::from(NameMap { items: vec![] })
+    }
+
+    #[test]
+    fn name_subsection_ids() {
+        let subsections = [
+            NameSubSection::Label(empty_names()),
+            NameSubSection::Type(empty_names()),
+            NameSubSection::Table(empty_names()),
+            NameSubSection::Memory(empty_names()),
+            NameSubSection::Global(empty_names()),
+            NameSubSection::Elem(empty_names()),
+            NameSubSection::Data(empty_names()),
+            NameSubSection::Field(empty_names()),
+            NameSubSection::Tag(empty_names()),
+        ];
+        for (sub, id) in subsections.iter().zip(3..) {
+            // Subsection id, size and an empty map.
+            let expected = [id, 1, 0];
+            let mut encoded = Vec::new();
+            sub.encode(&mut encoded).unwrap();
+            assert_eq!(encoded, expected);
+            let decoded = NameSubSection::decode(&mut SliceReader::new(&expected, None, None));
+            assert_eq!(&decoded.unwrap(), sub);
+        }
+
+        let unknown = NameSubSection::Unknown(UnknownNameSubSection {
+            id: 12,
+            data: RawBlob {
+                contents: vec![0x01, 0x02],
+            },
+        });
+        let expected = [12, 2, 0x01, 0x02];
+        let mut encoded = Vec::new();
+        unknown.encode(&mut encoded).unwrap();
+        assert_eq!(encoded, expected);
+        let decoded = NameSubSection::decode(&mut SliceReader::new(&expected, None, None));
+        assert_eq!(decoded.unwrap(), unknown);
+    }
+}
@@ -0,0 +1,289 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::{Blob, WasmbinCountable};
+use crate::indices::FuncId;
+use crate::io::{Decode, DecodeError, Encode, EncodeError, Wasmbin};
+use crate::sections::{payload, CustomSection, FuncBody, ImportDesc};
+use crate::visit::Visit;
+use crate::Module;
+use alloc::vec;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+use core::convert::TryFrom;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CodeMetadataError {
+    #[error("{0}")]
+    Decode(#[from] DecodeError),
+
+    #[error("{0}")]
+    Encode(#[from] EncodeError),
+
+    #[error("Code metadata offset 0x{0:X} doesn't point to an instruction")]
+    InvalidOffset(u32),
+
+    #[error("Instruction index {0} is out of bounds of the function body")]
+    InvalidIndex(usize),
+
+    #[error("Expected {expected} instruction indices, got {actual}")]
+    IndexCountMismatch { expected: usize, actual: usize },
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CodeMetadataEntry<T: Decode> {
+    /// Byte offset of the instruction from the start of the function body.
+    pub offset: u32,
+    pub data: Blob<T>,
+}
+
+impl<T: Decode> WasmbinCountable for CodeMetadataEntry<T> {}
+
+/// Code metadata of a single function.
+///
+/// Entries refer to instructions by byte offsets into the function body,
+/// so they're not updated automatically when the body is modified. To keep
+/// them in sync, resolve them with [`instruction_indices`](Self::instruction_indices)
+/// before modifying the body, and write them back with
+/// [`set_instruction_indices`](Self::set_instruction_indices) afterwards,
+/// or modify bodies via [`Module::modify_func_bodies`] that does both.
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct CodeMetadataFunc<T: Decode> {
+    pub func: FuncId,
+    pub entries: Vec<CodeMetadataEntry<T>>,
+}
+
+impl<T: Decode> WasmbinCountable for CodeMetadataFunc<T> {}
+
+/// Byte offsets of each instruction in `body.expr`.
+///
+/// The offsets are computed against the original bytes if the body wasn't
+/// modified, or against the bytes it will be re-encoded to otherwise.
+pub fn instruction_offsets(body: &Blob<FuncBody>) -> Result<Vec<u32>, CodeMetadataError> {
+    let encoded;
+    let bytes = match body.try_as_raw() {
+        Ok(raw) => raw,
+        Err(body) => {
+            let mut buf = Vec::new();
+            body.encode(&mut buf)?;
+            encoded = buf;
+            &encoded
+        }
+    };
+    let (_, mut offsets) = FuncBody::decode_with_offsets(bytes)?;
+    // Drop the offset of the final `end`, which isn't part of `expr`.
+    offsets.pop();
+    offsets
+        .into_iter()
+        .map(|offset| Ok(u32::try_from(offset).map_err(EncodeError::from)?))
+        .collect()
+}
+
+impl<T: Decode> CodeMetadataFunc<T> {
+    /// Resolves byte offsets of entries to instruction indices in `body.expr`.
+    pub fn instruction_indices(
+        &self,
+        body: &Blob<FuncBody>,
+    ) -> Result<Vec<usize>, CodeMetadataError> {
+        let offsets = instruction_offsets(body)?;
+        self.entries
+            .iter()
+            .map(|entry| {
+                offsets
+                    .binary_search(&entry.offset)
+                    .map_err(|_| CodeMetadataError::InvalidOffset(entry.offset))
+            })
+            .collect()
+    }
+
+    /// Rewrites byte offsets of entries to point to the given instruction
+    /// indices in `body.expr`, e.g. after the body was modified.
+    ///
+    /// `indices` must have an index for each entry. Entries are left intact
+    /// if any of the indices is invalid.
+    pub fn set_instruction_indices(
+        &mut self,
+        body: &Blob<FuncBody>,
+        indices: &[usize],
+    ) -> Result<(), CodeMetadataError> {
+        if indices.len() != self.entries.len() {
+            return Err(CodeMetadataError::IndexCountMismatch {
+                expected: self.entries.len(),
+                actual: indices.len(),
+            });
+        }
+        let new_offsets = offsets_of_indices(&instruction_offsets(body)?, indices)?;
+        self.set_offsets(new_offsets);
+        Ok(())
+    }
+
+    fn set_offsets(&mut self, offsets: impl IntoIterator<Item = u32>) {
+        for (entry, offset) in self.entries.iter_mut().zip(offsets) {
+            entry.offset = offset;
+        }
+    }
+}
+
+fn offsets_of_indices(offsets: &[u32], indices: &[usize]) -> Result<Vec<u32>, CodeMetadataError> {
+    indices
+        .iter()
+        .map(|&index| {
+            offsets
+                .get(index)
+                .copied()
+                .ok_or(CodeMetadataError::InvalidIndex(index))
+        })
+        .collect()
+}
+
+/// Index in the Code section of the body of the given function, if it has
+/// one.
+fn body_index(func: FuncId, imported_funcs: usize, bodies_len: usize) -> Option<usize> {
+    usize::try_from(func.index)
+        .ok()?
+        .checked_sub(imported_funcs)
+        .filter(|&index| index < bodies_len)
+}
+
+/// Appends instruction indices of entries of `funcs` to the ones of their
+/// bodies.
+fn resolve_indices<T: Decode>(
+    funcs: &[CodeMetadataFunc<T>],
+    bodies: &[Blob<FuncBody>],
+    imported_funcs: usize,
+    indices: &mut [Vec<usize>],
+) -> Result<(), CodeMetadataError> {
+    for func in funcs {
+        if let Some(index) = body_index(func.func, imported_funcs, bodies.len()) {
+            indices[index].extend(func.instruction_indices(&bodies[index])?);
+        }
+    }
+    Ok(())
+}
+
+/// Takes the new offsets of entries of `funcs` from the ones of their
+/// bodies, in the same order as [`resolve_indices`] appended them.
+fn rewrite_offsets<T: Decode>(
+    funcs: &mut [CodeMetadataFunc<T>],
+    imported_funcs: usize,
+    offsets: &mut [vec::IntoIter<u32>],
+) {
+    for func in funcs {
+        if let Some(index) = body_index(func.func, imported_funcs, offsets.len()) {
+            let count = func.entries.len();
+            func.set_offsets(offsets[index].by_ref().take(count));
+        }
+    }
+}
+
+impl Module {
+    /// Calls `f` on each function body in the Code section, keeping code
+    /// metadata, such as branch hints, attached to the same instructions.
+    ///
+    /// Along with the index and the body, `f` gets the instruction indices
+    /// of all code metadata entries of the function. It has to update them
+    /// to match its changes, e.g. increment the ones after an inserted
+    /// instruction. Byte offsets of the entries are then rewritten to point
+    /// to the updated indices in the re-encoded bodies.
+    ///
+    /// Entries of functions without a body are left intact, and so is the
+    /// whole code metadata if any of the updated indices is invalid.
+    pub fn modify_func_bodies(
+        &mut self,
+        mut f: impl FnMut(usize, &mut FuncBody, &mut [usize]),
+    ) -> Result<(), CodeMetadataError> {
+        let imported_funcs = match self.find_std_section::<payload::Import>() {
+            Some(imports) => imports
+                .try_contents()?
+                .iter()
+                .filter(|import| matches!(import.desc, ImportDesc::Func(_)))
+                .count(),
+            None => 0,
+        };
+        let Some(code) = self.find_std_section::<payload::Code>() else {
+            return Ok(());
+        };
+        let bodies = code.try_contents()?;
+        let mut indices = vec![Vec::new(); bodies.len()];
+        for section in &self.sections {
+            let Some(custom) = section.try_as::<payload::Custom>() else {
+                continue;
+            };
+            match custom.try_contents()? {
+                CustomSection::BranchHint(funcs) => {
+                    resolve_indices(funcs.try_contents()?, bodies, imported_funcs, &mut indices)?;
+                }
+                CustomSection::CodeMetadata(section) => {
+                    let funcs = section.data.try_contents()?;
+                    resolve_indices(funcs, bodies, imported_funcs, &mut indices)?;
+                }
+                _ => {}
+            }
+        }
+
+        let Some(code) = self.find_std_section_mut::<payload::Code>() else {
+            return Ok(());
+        };
+        let mut offsets = Vec::with_capacity(indices.len());
+        for (i, (body, indices)) in code
+            .try_contents_mut()?
+            .iter_mut()
+            .zip(&mut indices)
+            .enumerate()
+        {
+            f(i, body.try_contents_mut()?, indices);
+            offsets.push(match indices.is_empty() {
+                true => Vec::new(),
+                false => offsets_of_indices(&instruction_offsets(body)?, indices)?,
+            });
+        }
+
+        let mut offsets: Vec<_> = offsets.into_iter().map(Vec::into_iter).collect();
+        for section in &mut self.sections {
+            let Some(custom) = section.try_as_mut::<payload::Custom>() else {
+                continue;
+            };
+            // Avoid marking other custom sections as modified.
+            if !matches!(
+                custom.try_contents()?,
+                CustomSection::BranchHint(_) | CustomSection::CodeMetadata(_)
+            ) {
+                continue;
+            }
+            match custom.try_contents_mut()? {
+                CustomSection::BranchHint(funcs) => {
+                    rewrite_offsets(funcs.try_contents_mut()?, imported_funcs, &mut offsets);
+                }
+                CustomSection::CodeMetadata(section) => {
+                    let funcs = section.data.try_contents_mut()?;
+                    rewrite_offsets(funcs, imported_funcs, &mut offsets);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum BranchHint {
+    Unlikely = 0,
+    Likely = 1,
+}
@@ -0,0 +1,72 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::{Blob, WasmbinCountable};
+use crate::io::{Decode, DecodeError, Encode, EncodeError, Wasmbin};
+use crate::sections::{decode_subsections, ImportPath};
+use crate::visit::Visit;
+use crate::wasmbin_discriminants;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct MemInfo {
+    pub memory_size: u32,
+    pub memory_align: u32,
+    pub table_size: u32,
+    pub table_align: u32,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ExportInfo {
+    pub name: String,
+    pub flags: u32,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct ImportInfo {
+    pub path: ImportPath,
+    pub flags: u32,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum DylinkSubSection {
+    MemInfo(Blob<MemInfo>) = 1,
+    Needed(Blob<Vec<String>>) = 2,
+    ExportInfo(Blob<Vec<ExportInfo>>) = 3,
+    ImportInfo(Blob<Vec<ImportInfo>>) = 4,
+}
+
+impl Encode for [DylinkSubSection] {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        for sub in self {
+            sub.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Vec<DylinkSubSection> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        decode_subsections(r)
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::builtins::{Blob, WasmbinCountable};
+use crate::indices::{DataId, FuncId, GlobalId, TableId, TagId};
+use crate::io::{
+    Decode, DecodeError, DecodeErrorKind, Encode, EncodeError, EncodeErrorKind, PathItem, Wasmbin,
+};
+use crate::sections::decode_subsections;
+use crate::visit::Visit;
+use crate::wasmbin_discriminants;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
+use arbitrary::Arbitrary;
+use core::convert::TryFrom;
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct SegmentInfo {
+    pub name: String,
+    pub align: u32,
+    pub flags: u32,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct InitFunc {
+    pub priority: u32,
+    pub symbol: u32,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum ComdatSym {
+    Data(DataId) = 0,
+    Func(FuncId) = 1,
+    Global(GlobalId) = 2,
+    Tag(TagId) = 3,
+    Table(TableId) = 4,
+    Section(u32) = 5,
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Comdat {
+    pub name: String,
+    pub flags: u32,
+    pub symbols: Vec<ComdatSym>,
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct DataSymbolDef {
+    pub segment: DataId,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Symbol kind along with its kind-specific payload.
+///
+/// Names of indexed symbols are present only when the symbol is defined or
+/// has [`SymbolInfo::EXPLICIT_NAME`] set, and data symbols have a
+/// definition only when they're not [`SymbolInfo::UNDEFINED`].
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum SymbolKind {
+    Func {
+        index: FuncId,
+        name: Option<String>,
+    },
+    Data {
+        name: String,
+        def: Option<DataSymbolDef>,
+    },
+    Global {
+        index: GlobalId,
+        name: Option<String>,
+    },
+    Section {
+        section: u32,
+    },
+    Tag {
+        index: TagId,
+        name: Option<String>,
+    },
+    Table {
+        index: TableId,
+        name: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct SymbolInfo {
+    pub flags: u32,
+    pub kind: SymbolKind,
+}
+
+impl SymbolInfo {
+    pub const BINDING_WEAK: u32 = 0x01;
+    pub const BINDING_LOCAL: u32 = 0x02;
+    pub const VISIBILITY_HIDDEN: u32 = 0x04;
+    pub const UNDEFINED: u32 = 0x10;
+    pub const EXPORTED: u32 = 0x20;
+    pub const EXPLICIT_NAME: u32 = 0x40;
+    pub const NO_STRIP: u32 = 0x80;
+    pub const TLS: u32 = 0x100;
+    pub const ABSOLUTE: u32 = 0x200;
+
+    pub fn is_undefined(&self) -> bool {
+        self.flags & Self::UNDEFINED != 0
+    }
+
+    fn has_name(&self) -> bool {
+        !self.is_undefined() || self.flags & Self::EXPLICIT_NAME != 0
+    }
+}
+
+fn encode_if_present<T: Encode>(
+    value: Option<&T>,
+    expected: bool,
+    w: &mut impl crate::io::Write,
+) -> Result<(), EncodeError> {
+    match (value, expected) {
+        (Some(value), true) => value.encode(w),
+        (None, false) => Ok(()),
+        _ => Err(EncodeErrorKind::InvalidValue("Symbol contents don't match its flags").into()),
+    }
+}
+
+fn decode_if_present<T: Decode>(
+    expected: bool,
+    r: &mut impl crate::io::Read,
+) -> Result<Option<T>, DecodeError> {
+    Ok(match expected {
+        true => Some(T::decode(r)?),
+        false => None,
+    })
+}
+
+impl Encode for SymbolInfo {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        let (kind, index, name) = match &self.kind {
+            SymbolKind::Func { index, name } => (0_u8, index.index, name),
+            SymbolKind::Global { index, name } => (2, index.index, name),
+            SymbolKind::Tag { index, name } => (4, index.index, name),
+            SymbolKind::Table { index, name } => (5, index.index, name),
+            SymbolKind::Data { name, def } => {
+                1_u8.encode(w)?;
+                self.flags.encode(w)?;
+                name.encode(w)?;
+                return encode_if_present(def.as_ref(), !self.is_undefined(), w);
+            }
+            SymbolKind::Section { section } => {
+                3_u8.encode(w)?;
+                self.flags.encode(w)?;
+                return section.encode(w);
+            }
+        };
+        kind.encode(w)?;
+        self.flags.encode(w)?;
+        index.encode(w)?;
+        encode_if_present(name.as_ref(), self.has_name(), w)
+    }
+}
+
+impl Decode for SymbolInfo {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let kind = u8::decode(r)?;
+        let mut info = SymbolInfo {
+            flags: u32::decode(r).map_err(|err| err.in_path(PathItem::Name("flags")))?,
+            kind: SymbolKind::Section { section: 0 },
+        };
+        let has_name = info.has_name();
+        info.kind = match kind {
+            0 => SymbolKind::Func {
+                index: FuncId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            1 => SymbolKind::Data {
+                name: String::decode(r)?,
+                def: decode_if_present(!info.is_undefined(), r)?,
+            },
+            2 => SymbolKind::Global {
+                index: GlobalId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            3 => SymbolKind::Section {
+                section: u32::decode(r)?,
+            },
+            4 => SymbolKind::Tag {
+                index: TagId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            5 => SymbolKind::Table {
+                index: TableId::decode(r)?,
+                name: decode_if_present(has_name, r)?,
+            },
+            _ => {
+                return Err(DecodeErrorKind::UnsupportedDiscriminant {
+                    ty: "SymbolKind",
+                    discriminant: kind.into(),
+                }
+                .into())
+            }
+        };
+        Ok(info)
+    }
+}
+
+impl WasmbinCountable for SymbolInfo {}
+
+// Presence of names and data definitions depends on flags, so generate
+// them together to avoid symbols that can't be encoded.
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for SymbolInfo {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let mut info = SymbolInfo {
+            flags: u.arbitrary()?,
+            kind: SymbolKind::Section { section: 0 },
+        };
+        let has_name = info.has_name();
+        let name = |u: &mut arbitrary::Unstructured<'a>| match has_name {
+            true => u.arbitrary().map(Some),
+            false => Ok(None),
+        };
+        info.kind = match u.int_in_range(0..=5)? {
+            0 => SymbolKind::Func {
+                index: u.arbitrary()?,
+                name: name(u)?,
+            },
+            1 => SymbolKind::Data {
+                name: u.arbitrary()?,
+                def: match info.is_undefined() {
+                    true => None,
+                    false => Some(u.arbitrary()?),
+                },
+            },
+            2 => SymbolKind::Global {
+                index: u.arbitrary()?,
+                name: name(u)?,
+            },
+            3 => SymbolKind::Section {
+                section: u.arbitrary()?,
+            },
+            4 => SymbolKind::Tag {
+                index: u.arbitrary()?,
+                name: name(u)?,
+            },
+            _ => SymbolKind::Table {
+                index: u.arbitrary()?,
+                name: name(u)?,
+            },
+        };
+        Ok(info)
+    }
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum LinkingSubSection {
+    SegmentInfo(Blob<Vec<SegmentInfo>>) = 5,
+    InitFuncs(Blob<Vec<InitFunc>>) = 6,
+    ComdatInfo(Blob<Vec<Comdat>>) = 7,
+    SymbolTable(Blob<Vec<SymbolInfo>>) = 8,
+}
+
+impl Encode for [LinkingSubSection] {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        for sub in self {
+            sub.encode(w)?;
+        }
+        Ok(())
+    }
+}
+
+impl Decode for Vec<LinkingSubSection> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        decode_subsections(r)
+    }
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Linking {
+    pub version: u32,
+    pub subsections: Vec<LinkingSubSection>,
+}
+
+impl Linking {
+    pub const VERSION: u32 = 2;
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum RelocType {
+    FunctionIndexLeb = 0,
+    TableIndexSleb = 1,
+    TableIndexI32 = 2,
+    MemoryAddrLeb = 3,
+    MemoryAddrSleb = 4,
+    MemoryAddrI32 = 5,
+    TypeIndexLeb = 6,
+    GlobalIndexLeb = 7,
+    FunctionOffsetI32 = 8,
+    SectionOffsetI32 = 9,
+    TagIndexLeb = 10,
+    MemoryAddrRelSleb = 11,
+    TableIndexRelSleb = 12,
+    GlobalIndexI32 = 13,
+    MemoryAddrLeb64 = 14,
+    MemoryAddrSleb64 = 15,
+    MemoryAddrI64 = 16,
+    MemoryAddrRelSleb64 = 17,
+    TableIndexSleb64 = 18,
+    TableIndexI64 = 19,
+    TableNumberLeb = 20,
+    MemoryAddrTlsSleb = 21,
+    FunctionOffsetI64 = 22,
+    MemoryAddrLocrelI32 = 23,
+    TableIndexRelSleb64 = 24,
+    MemoryAddrTlsSleb64 = 25,
+    FunctionIndexI32 = 26,
+}
+
+enum AddendKind {
+    None,
+    I32,
+    I64,
+}
+
+impl RelocType {
+    fn addend_kind(self) -> AddendKind {
+        match self {
+            RelocType::MemoryAddrLeb
+            | RelocType::MemoryAddrSleb
+            | RelocType::MemoryAddrI32
+            | RelocType::FunctionOffsetI32
+            | RelocType::SectionOffsetI32
+            | RelocType::MemoryAddrRelSleb
+            | RelocType::MemoryAddrTlsSleb
+            | RelocType::MemoryAddrLocrelI32 => AddendKind::I32,
+            RelocType::MemoryAddrLeb64
+            | RelocType::MemoryAddrSleb64
+            | RelocType::MemoryAddrI64
+            | RelocType::MemoryAddrRelSleb64
+            | RelocType::FunctionOffsetI64
+            | RelocType::MemoryAddrTlsSleb64 => AddendKind::I64,
+            _ => AddendKind::None,
+        }
+    }
+
+    pub fn has_addend(self) -> bool {
+        !matches!(self.addend_kind(), AddendKind::None)
+    }
+}
+
+/// Relocation entry.
+///
+/// `addend` is encoded only for relocation types that have one, and must
+/// be zero for all others.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+pub struct RelocEntry {
+    pub ty: RelocType,
+    pub offset: u32,
+    pub index: u32,
+    pub addend: i64,
+}
+
+impl Encode for RelocEntry {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        self.ty.encode(w)?;
+        self.offset.encode(w)?;
+        self.index.encode(w)?;
+        match self.ty.addend_kind() {
+            AddendKind::None if self.addend == 0 => Ok(()),
+            AddendKind::None => {
+                Err(EncodeErrorKind::InvalidValue("Relocation type doesn't have an addend").into())
+            }
+            AddendKind::I32 => i32::try_from(self.addend)?.encode(w),
+            AddendKind::I64 => self.addend.encode(w),
+        }
+    }
+}
+
+impl Decode for RelocEntry {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let ty = RelocType::decode(r).map_err(|err| err.in_path(PathItem::Name("ty")))?;
+        let offset = u32::decode(r).map_err(|err| err.in_path(PathItem::Name("offset")))?;
+        let index = u32::decode(r).map_err(|err| err.in_path(PathItem::Name("index")))?;
+        let addend = match ty.addend_kind() {
+            AddendKind::None => Ok(0),
+            AddendKind::I32 => i32::decode(r).map(i64::from),
+            AddendKind::I64 => i64::decode(r),
+        }
+        .map_err(|err| err.in_path(PathItem::Name("addend")))?;
+        Ok(RelocEntry {
+            ty,
+            offset,
+            index,
+            addend,
+        })
+    }
+}
+
+impl WasmbinCountable for RelocEntry {}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> Arbitrary<'a> for RelocEntry {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let ty = RelocType::arbitrary(u)?;
+        Ok(RelocEntry {
+            ty,
+            offset: u.arbitrary()?,
+            index: u.arbitrary()?,
+            addend: match ty.addend_kind() {
+                AddendKind::None => 0,
+                AddendKind::I32 => i32::arbitrary(u)?.into(),
+                AddendKind::I64 => u.arbitrary()?,
+            },
+        })
+    }
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct Reloc {
+    /// Index of the section these relocations apply to.
+    pub section: u32,
+    pub entries: Vec<RelocEntry>,
+}
@@ -0,0 +1,295 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+use crate::builtins::{peek_usize, Blob};
+use crate::io::{
+    Decode, DecodeContext, DecodeError, DecodeErrorKind, DecodeOptions, Limit, PathItem,
+};
+use crate::module::{MagicAndVersion, MAGIC_AND_VERSION};
+use crate::sections::{FuncBody, Kind, Section, SectionOrderTracker};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Item produced by [`Decoder`] as soon as enough input is available.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Event {
+    /// A complete section other than the Code section.
+    Section(Section),
+    /// Start of the Code section, followed by `count` [`Event::FuncBody`] items.
+    CodeSectionStart { count: usize },
+    /// A complete function body from the Code section.
+    FuncBody(Blob<FuncBody>),
+}
+
+#[derive(Debug)]
+enum State {
+    Header,
+    Sections,
+    CodeCount { end: usize },
+    Code { remaining: usize, end: usize },
+}
+
+/// Push-based decoder for modules that arrive in chunks.
+///
+/// Feed input with [`Decoder::push`] and drain decoded items with
+/// [`Decoder::next_event`] until it returns `Ok(None)`. Once an error is
+/// returned, the decoder shouldn't be used anymore.
+#[derive(Debug)]
+pub struct Decoder {
+    buf: Vec<u8>,
+    /// Offset of the start of `buf` in the whole input.
+    base: usize,
+    /// Position of the first unprocessed byte in `buf`.
+    cursor: usize,
+    state: State,
+    section_order_tracker: SectionOrderTracker,
+    section_index: usize,
+    func_index: usize,
+    context: Option<DecodeContext>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unexpected_eof() -> DecodeError {
+    DecodeErrorKind::Io(crate::io::IoErrorKind::UnexpectedEof.into()).into()
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder {
+            buf: Vec::new(),
+            base: 0,
+            cursor: 0,
+            state: State::Header,
+            section_order_tracker: SectionOrderTracker::default(),
+            section_index: 0,
+            func_index: 0,
+            context: None,
+        }
+    }
+
+    /// Like [`Decoder::new`], but applies the given options, as
+    /// [`Module::decode_with_options`](crate::Module::decode_with_options)
+    /// does.
+    pub fn with_options(options: &DecodeOptions) -> Self {
+        Decoder {
+            context: Some(DecodeContext::new(options)),
+            ..Self::new()
+        }
+    }
+
+    /// Appends a chunk of input.
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.buf.drain(..self.cursor);
+        self.base += self.cursor;
+        self.cursor = 0;
+        self.buf.extend_from_slice(chunk);
+    }
+
+    /// Offset of the next unprocessed byte in the whole input.
+    pub fn position(&self) -> usize {
+        self.base + self.cursor
+    }
+
+    /// Decodes the next item, or returns `Ok(None)` if more input is needed.
+    pub fn next_event(&mut self) -> Result<Option<Event>, DecodeError> {
+        let pos = self.position();
+        self.step()
+            .map_err(|err| err.in_path(PathItem::Variant("Module")).at_offset(pos))
+    }
+
+    /// Checks that the input ended on a module boundary.
+    ///
+    /// Should be called once all the input was pushed and
+    /// [`Decoder::next_event`] returned `Ok(None)`.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.state {
+            State::Sections if self.cursor == self.buf.len() => Ok(()),
+            _ => Err(unexpected_eof()
+                .in_path(PathItem::Variant("Module"))
+                .at_offset(self.base + self.buf.len())),
+        }
+    }
+
+    fn track_allocation(&self, bytes: usize) -> Result<(), DecodeError> {
+        match &self.context {
+            Some(context) => context.track_allocation(bytes),
+            None => Ok(()),
+        }
+    }
+
+    fn in_section_path(&self, err: DecodeError) -> DecodeError {
+        err.in_path(PathItem::Index(self.section_index))
+            .in_path(PathItem::Name("sections"))
+    }
+
+    fn in_func_body_path(&self, err: DecodeError) -> DecodeError {
+        self.in_section_path(
+            err.in_path(PathItem::Index(self.func_index))
+                .in_path(PathItem::Variant("Section::Code")),
+        )
+    }
+
+    fn step(&mut self) -> Result<Option<Event>, DecodeError> {
+        loop {
+            match self.state {
+                State::Header => {
+                    let rest = &self.buf[self.cursor..];
+                    if rest.len() < MAGIC_AND_VERSION.len() {
+                        return Ok(None);
+                    }
+                    MagicAndVersion::decode(&mut &rest[..MAGIC_AND_VERSION.len()])
+                        .map_err(|err| err.in_path(PathItem::Name("magic_and_version")))?;
+                    self.cursor += MAGIC_AND_VERSION.len();
+                    self.state = State::Sections;
+                }
+                State::Sections => return self.step_section(),
+                State::CodeCount { end } => return self.step_code_count(end),
+                State::Code { remaining: 0, end } => {
+                    if self.position() != end {
+                        return Err(self.in_section_path(DecodeErrorKind::UnrecognizedData.into()));
+                    }
+                    self.section_index += 1;
+                    self.state = State::Sections;
+                }
+                State::Code { remaining, end } => return self.step_func_body(remaining, end),
+            }
+        }
+    }
+
+    fn step_section(&mut self) -> Result<Option<Event>, DecodeError> {
+        let Some((&disc, rest)) = self.buf[self.cursor..].split_first() else {
+            return Ok(None);
+        };
+        let Some((size, size_len)) =
+            peek_usize(rest, false).map_err(|err| self.in_section_path(err))?
+        else {
+            return Ok(None);
+        };
+        let kind = Kind::try_from(disc).map_err(|_| {
+            self.in_section_path(
+                DecodeErrorKind::UnsupportedDiscriminant {
+                    ty: "Section",
+                    discriminant: disc.into(),
+                }
+                .into(),
+            )
+        })?;
+        (|| {
+            kind.require_feature(self.context.as_ref())?;
+            match &self.context {
+                Some(context) => context.check_limit(Limit::Sections, self.section_index + 1),
+                None => Ok(()),
+            }
+        })()
+        .map_err(|err| self.in_section_path(err))?;
+        let contents = &rest[size_len..];
+        // Function bodies are yielded one by one instead of waiting for the
+        // whole Code section.
+        let is_code = matches!(kind, Kind::Code);
+        if !is_code && contents.len() < size {
+            return Ok(None);
+        }
+        self.section_order_tracker
+            .try_add_kind(kind)
+            .map_err(|err| self.in_section_path(err.into()))?;
+        if is_code {
+            self.cursor += 1 + size_len;
+            let end = self.position() + size;
+            self.state = State::CodeCount { end };
+            return self.step_code_count(end);
+        }
+        self.track_allocation(size)
+            .map_err(|err| self.in_section_path(err))?;
+        let raw = contents[..size].to_vec();
+        let offset = self.position() + 1 + size_len;
+        self.cursor += 1 + size_len + size;
+        self.section_index += 1;
+        Ok(Some(Event::Section(Section::from_raw(
+            kind,
+            raw,
+            Some(offset),
+            self.context.as_ref(),
+        ))))
+    }
+
+    /// Returns bytes available before `end`, and whether they're all there.
+    fn available_until(&self, end: usize) -> (&[u8], bool) {
+        let rest = &self.buf[self.cursor..];
+        let limit = end - self.position();
+        match rest.get(..limit) {
+            Some(rest) => (rest, true),
+            None => (rest, false),
+        }
+    }
+
+    fn step_code_count(&mut self, end: usize) -> Result<Option<Event>, DecodeError> {
+        let (rest, complete) = self.available_until(end);
+        let Some((count, count_len)) =
+            peek_usize(rest, complete).map_err(|err| self.in_section_path(err))?
+        else {
+            return Ok(None);
+        };
+        if let Some(context) = &self.context {
+            context
+                .check_limit(Limit::VecLen, count)
+                .map_err(|err| self.in_section_path(err))?;
+        }
+        self.cursor += count_len;
+        self.func_index = 0;
+        self.state = State::Code {
+            remaining: count,
+            end,
+        };
+        Ok(Some(Event::CodeSectionStart { count }))
+    }
+
+    fn step_func_body(
+        &mut self,
+        remaining: usize,
+        end: usize,
+    ) -> Result<Option<Event>, DecodeError> {
+        let (rest, complete) = self.available_until(end);
+        let Some((size, size_len)) =
+            peek_usize(rest, complete).map_err(|err| self.in_func_body_path(err))?
+        else {
+            return Ok(None);
+        };
+        let contents = &rest[size_len..];
+        if contents.len() < size {
+            return match complete {
+                true => Err(self.in_func_body_path(unexpected_eof())),
+                false => Ok(None),
+            };
+        }
+        self.track_allocation(size)
+            .map_err(|err| self.in_func_body_path(err))?;
+        let raw = contents[..size].to_vec();
+        let offset = self.position() + size_len;
+        self.cursor += size_len + size;
+        self.func_index += 1;
+        self.state = State::Code {
+            remaining: remaining - 1,
+            end,
+        };
+        Ok(Some(Event::FuncBody(Blob::from_input(
+            raw,
+            Some(offset),
+            self.context.as_ref(),
+        ))))
+    }
+}
@@ -14,20 +14,26 @@
 
 use crate::builtins::WasmbinCountable;
 use crate::indices::TypeId;
-use crate::io::{Decode, DecodeError, DecodeWithDiscriminant, Encode, PathItem, Wasmbin};
+use crate::io::{
+    require_feature, Decode, DecodeContext, DecodeError, DecodeWithDiscriminant, Encode,
+    EncodeError, Feature, IoError, PathItem, Read, Wasmbin,
+};
 use crate::visit::Visit;
 use crate::wasmbin_discriminants;
+use alloc::vec::Vec;
+#[cfg(feature = "arbitrary")]
 use arbitrary::Arbitrary;
-use std::convert::TryFrom;
-use std::fmt::{self, Debug, Formatter};
+use core::convert::TryFrom;
+use core::fmt::{self, Debug, Formatter};
 
 const OP_CODE_EMPTY_BLOCK: u8 = 0x40;
 
 #[wasmbin_discriminants]
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum ValueType {
-    #[cfg(feature = "simd")]
+    #[wasmbin(requires = Feature::Simd)]
     V128 = 0x7B,
     F64 = 0x7C,
     F32 = 0x7D,
@@ -36,7 +42,8 @@ pub enum ValueType {
     Ref(RefType),
 }
 
-#[derive(Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
 pub enum BlockType {
     Empty,
@@ -45,7 +52,7 @@ pub enum BlockType {
 }
 
 impl Encode for BlockType {
-    fn encode(&self, w: &mut impl std::io::Write) -> std::io::Result<()> {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
         match self {
             BlockType::Empty => OP_CODE_EMPTY_BLOCK.encode(w),
             BlockType::Value(ty) => ty.encode(w),
@@ -55,7 +62,7 @@ impl Encode for BlockType {
 }
 
 impl Decode for BlockType {
-    fn decode(r: &mut impl std::io::Read) -> Result<Self, DecodeError> {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
         let discriminant = u8::decode(r)?;
         if discriminant == OP_CODE_EMPTY_BLOCK {
             return Ok(BlockType::Empty);
@@ -65,26 +72,63 @@ impl Decode for BlockType {
         {
             return Ok(BlockType::Value(ty));
         }
-        let index = (move || -> Result<_, DecodeError> {
-            // We have already read one byte that could've been either a
-            // discriminant or a part of an s33 LEB128 specially used for
-            // type indices.
-            //
-            // To recover the LEB128 sequence, we need to chain it back.
-            let buf = [discriminant];
-            let mut r = std::io::Read::chain(&buf[..], r);
-            let as_i64 = i64::decode(&mut r)?;
-            // These indices are encoded as positive signed integers.
-            // Convert them to unsigned integers and error out if they're out of range.
-            let index = u32::try_from(as_i64)?;
-            Ok(index)
-        })()
-        .map_err(|err| err.in_path(PathItem::Variant("BlockType::MultiValue")))?;
-        Ok(BlockType::MultiValue(TypeId { index }))
+        decode_type_index(discriminant, r)
+            .map(BlockType::MultiValue)
+            .map_err(|err| err.in_path(PathItem::Variant("BlockType::MultiValue")))
+    }
+}
+
+/// Reader that yields a byte that was already consumed before the rest of
+/// the input.
+struct Unread<'a, R: ?Sized> {
+    byte: Option<u8>,
+    rest: &'a mut R,
+}
+
+impl<R: Read> Read for Unread<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, IoError> {
+        match (self.byte, buf.first_mut()) {
+            (Some(byte), Some(dest)) => {
+                *dest = byte;
+                self.byte = None;
+                Ok(1)
+            }
+            _ => self.rest.read(buf),
+        }
+    }
+
+    fn position(&self) -> Option<usize> {
+        let pos = self.rest.position()?;
+        Some(pos - usize::from(self.byte.is_some()))
+    }
+
+    fn decode_context(&self) -> Option<&DecodeContext> {
+        self.rest.decode_context()
     }
 }
 
-#[derive(Wasmbin, WasmbinCountable, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+pub(crate) fn decode_type_index(
+    first_byte: u8,
+    r: &mut impl crate::io::Read,
+) -> Result<TypeId, DecodeError> {
+    // We have already read one byte that could've been either a
+    // discriminant or a part of an s33 LEB128 specially used for
+    // type indices.
+    //
+    // To recover the LEB128 sequence, we need to chain it back.
+    let mut r = Unread {
+        byte: Some(first_byte),
+        rest: r,
+    };
+    let as_i64 = i64::decode(&mut r)?;
+    // These indices are encoded as positive signed integers.
+    // Convert them to unsigned integers and error out if they're out of range.
+    let index = u32::try_from(as_i64)?;
+    Ok(TypeId { index })
+}
+
+#[derive(Wasmbin, WasmbinCountable, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[wasmbin(discriminant = 0x60)]
 pub struct FuncType {
     pub params: Vec<ValueType>,
@@ -110,17 +154,111 @@ impl Debug for FuncType {
     }
 }
 
-#[derive(Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum StorageType {
+    I16 = 0x77,
+    I8 = 0x78,
+    Value(ValueType),
+}
+
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct FieldType {
+    pub storage_type: StorageType,
+    pub mutable: bool,
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum CompositeType {
+    #[wasmbin(requires = Feature::Gc)]
+    Array(FieldType) = 0x5E,
+    #[wasmbin(requires = Feature::Gc)]
+    Struct(Vec<FieldType>) = 0x5F,
+    // Function types already carry their own 0x60 discriminant.
+    Func(FuncType),
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum SubType {
+    #[wasmbin(requires = Feature::Gc)]
+    SubFinal {
+        supertypes: Vec<TypeId>,
+        ty: CompositeType,
+    } = 0x4F,
+    #[wasmbin(requires = Feature::Gc)]
+    Sub {
+        supertypes: Vec<TypeId>,
+        ty: CompositeType,
+    } = 0x50,
+    // Shorthand for a final type without supertypes.
+    // Variants above are kept in ascending order so that the implicit
+    // discriminant of this one doesn't clash with them under `nightly`.
+    Final(CompositeType),
+}
+
+#[wasmbin_discriminants]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+#[repr(u8)]
+pub enum RecGroup {
+    #[wasmbin(requires = Feature::Gc)]
+    Multi(Vec<SubType>) = 0x4E,
+    // Shorthand for a recursion group with a single type.
+    Single(SubType),
+}
+
+impl From<FuncType> for CompositeType {
+    fn from(ty: FuncType) -> Self {
+        CompositeType::Func(ty)
+    }
+}
+
+impl From<CompositeType> for SubType {
+    fn from(ty: CompositeType) -> Self {
+        SubType::Final(ty)
+    }
+}
+
+impl From<SubType> for RecGroup {
+    fn from(ty: SubType) -> Self {
+        RecGroup::Single(ty)
+    }
+}
+
+/// Wraps a function type the way it's encoded in modules without the GC
+/// proposal, as a final type without supertypes alone in its group.
+impl From<FuncType> for RecGroup {
+    fn from(ty: FuncType) -> Self {
+        SubType::from(CompositeType::from(ty)).into()
+    }
+}
+
+/// Limits of a memory or a table.
+///
+/// Bounds are 64-bit to cover memories indexed by `i64` from the memory64
+/// proposal. Other memories and tables fail to encode if they don't fit in
+/// 32 bits.
+#[derive(PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct Limits {
-    pub min: u32,
-    pub max: Option<u32>,
+    pub min: u64,
+    pub max: Option<u64>,
 }
 
 impl Debug for Limits {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         write!(f, "{}..", self.min)?;
         if let Some(max) = self.max {
-            write!(f, "={}", max)?;
+            write!(f, "={max}")?;
         }
         Ok(())
     }
@@ -134,51 +272,349 @@ enum LimitsRepr {
     MinMax { min: u32, max: u32 } = 0x01,
 }
 
-encode_decode_as!(Limits, {
-    (Limits { min, max: None }) <=> (LimitsRepr::Min { min }),
-    (Limits { min, max: Some(max) }) <=> (LimitsRepr::MinMax { min, max }),
-});
+impl Limits {
+    fn to_repr32(&self) -> Result<LimitsRepr, core::num::TryFromIntError> {
+        let min = u32::try_from(self.min)?;
+        Ok(match self.max {
+            None => LimitsRepr::Min { min },
+            Some(max) => LimitsRepr::MinMax {
+                min,
+                max: u32::try_from(max)?,
+            },
+        })
+    }
+}
+
+impl From<LimitsRepr> for Limits {
+    fn from(repr: LimitsRepr) -> Self {
+        match repr {
+            LimitsRepr::Min { min } => Limits {
+                min: min.into(),
+                max: None,
+            },
+            LimitsRepr::MinMax { min, max } => Limits {
+                min: min.into(),
+                max: Some(max.into()),
+            },
+        }
+    }
+}
+
+impl Encode for Limits {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        self.to_repr32()?.encode(w)
+    }
+}
+
+impl Decode for Limits {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        LimitsRepr::decode(r).map(Limits::from)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum IndexType {
+    I32,
+    I64,
+}
 
-#[cfg(feature = "threads")]
 #[wasmbin_discriminants]
 #[derive(Wasmbin)]
 #[repr(u8)]
 enum MemTypeRepr {
     Unshared(LimitsRepr),
-    SharedMin { min: u32 } = 0x02,
-    SharedMinMax { min: u32, max: u32 } = 0x03,
+    #[wasmbin(requires = Feature::Threads)]
+    SharedMin {
+        min: u32,
+    } = 0x02,
+    #[wasmbin(requires = Feature::Threads)]
+    SharedMinMax {
+        min: u32,
+        max: u32,
+    } = 0x03,
+    #[wasmbin(requires = Feature::Memory64)]
+    Min64 {
+        min: u64,
+    } = 0x04,
+    #[wasmbin(requires = Feature::Memory64)]
+    MinMax64 {
+        min: u64,
+        max: u64,
+    } = 0x05,
+    #[wasmbin(requires = Feature::Memory64)]
+    #[wasmbin(requires = Feature::Threads)]
+    SharedMin64 {
+        min: u64,
+    } = 0x06,
+    #[wasmbin(requires = Feature::Memory64)]
+    #[wasmbin(requires = Feature::Threads)]
+    SharedMinMax64 {
+        min: u64,
+        max: u64,
+    } = 0x07,
 }
 
-#[cfg_attr(not(feature = "threads"), derive(Wasmbin))]
-#[derive(WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct MemType {
-    #[cfg(feature = "threads")]
     pub is_shared: bool,
+    pub index_type: IndexType,
     pub limits: Limits,
 }
 
-#[cfg(feature = "threads")]
-encode_decode_as!(MemType, {
-    (MemType { is_shared: false, limits: Limits { min, max: None } }) <=> (MemTypeRepr::Unshared(LimitsRepr::Min { min })),
-    (MemType { is_shared: false, limits: Limits { min, max: Some(max) } }) <=> (MemTypeRepr::Unshared(LimitsRepr::MinMax { min, max })),
-    (MemType { is_shared: true, limits: Limits { min, max: None } }) <=> (MemTypeRepr::SharedMin { min }),
-    (MemType { is_shared: true, limits: Limits { min, max: Some(max) } }) <=> (MemTypeRepr::SharedMinMax { min, max }),
-});
+impl Encode for MemType {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        let Limits { min, max } = self.limits;
+        match (self.index_type, self.is_shared, max) {
+            (IndexType::I32, false, _) => MemTypeRepr::Unshared(self.limits.to_repr32()?),
+            (IndexType::I32, true, _) => match self.limits.to_repr32()? {
+                LimitsRepr::Min { min } => MemTypeRepr::SharedMin { min },
+                LimitsRepr::MinMax { min, max } => MemTypeRepr::SharedMinMax { min, max },
+            },
+            (IndexType::I64, false, None) => MemTypeRepr::Min64 { min },
+            (IndexType::I64, false, Some(max)) => MemTypeRepr::MinMax64 { min, max },
+            (IndexType::I64, true, None) => MemTypeRepr::SharedMin64 { min },
+            (IndexType::I64, true, Some(max)) => MemTypeRepr::SharedMinMax64 { min, max },
+        }
+        .encode(w)
+    }
+}
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+impl Decode for MemType {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let (is_shared, index_type, limits) = match MemTypeRepr::decode(r)? {
+            MemTypeRepr::Unshared(limits) => (false, IndexType::I32, limits.into()),
+            MemTypeRepr::SharedMin { min } => {
+                (true, IndexType::I32, LimitsRepr::Min { min }.into())
+            }
+            MemTypeRepr::SharedMinMax { min, max } => {
+                (true, IndexType::I32, LimitsRepr::MinMax { min, max }.into())
+            }
+            MemTypeRepr::Min64 { min } => (false, IndexType::I64, Limits { min, max: None }),
+            MemTypeRepr::MinMax64 { min, max } => (
+                false,
+                IndexType::I64,
+                Limits {
+                    min,
+                    max: Some(max),
+                },
+            ),
+            MemTypeRepr::SharedMin64 { min } => (true, IndexType::I64, Limits { min, max: None }),
+            MemTypeRepr::SharedMinMax64 { min, max } => (
+                true,
+                IndexType::I64,
+                Limits {
+                    min,
+                    max: Some(max),
+                },
+            ),
+        };
+        Ok(MemType {
+            is_shared,
+            index_type,
+            limits,
+        })
+    }
+}
+
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Copy, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 #[repr(u8)]
-pub enum RefType {
+pub enum AbstractHeapType {
+    #[wasmbin(requires = Feature::Gc)]
+    #[wasmbin(requires = Feature::Exceptions)]
+    NoExn = 0x74,
+    #[wasmbin(requires = Feature::Gc)]
+    NoFunc = 0x73,
+    #[wasmbin(requires = Feature::Gc)]
+    NoExtern = 0x72,
+    #[wasmbin(requires = Feature::Gc)]
+    None = 0x71,
     Func = 0x70,
     Extern = 0x6F,
+    #[wasmbin(requires = Feature::Gc)]
+    Any = 0x6E,
+    #[wasmbin(requires = Feature::Gc)]
+    Eq = 0x6D,
+    #[wasmbin(requires = Feature::Gc)]
+    I31 = 0x6C,
+    #[wasmbin(requires = Feature::Gc)]
+    Struct = 0x6B,
+    #[wasmbin(requires = Feature::Gc)]
+    Array = 0x6A,
+    #[wasmbin(requires = Feature::Exceptions)]
+    Exn = 0x69,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub enum HeapType {
+    Abstract(AbstractHeapType),
+    Concrete(TypeId),
+}
+
+impl Debug for HeapType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            HeapType::Abstract(ty) => ty.fmt(f),
+            HeapType::Concrete(id) => id.fmt(f),
+        }
+    }
+}
+
+impl Encode for HeapType {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        match self {
+            HeapType::Abstract(ty) => ty.encode(w),
+            HeapType::Concrete(id) => i64::from(id.index).encode(w),
+        }
+    }
+}
+
+impl Decode for HeapType {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        let discriminant = u8::decode(r)?;
+        if let Some(ty) = AbstractHeapType::maybe_decode_with_discriminant(discriminant, r)? {
+            return Ok(HeapType::Abstract(ty));
+        }
+        (|| {
+            require_feature(r, Feature::FunctionReferences)?;
+            decode_type_index(discriminant, r)
+        })()
+        .map(HeapType::Concrete)
+        .map_err(|err| err.in_path(PathItem::Variant("HeapType::Concrete")))
+    }
+}
+
+const OP_CODE_REF: u8 = 0x64;
+
+const OP_CODE_REF_NULL: u8 = 0x63;
+
+#[derive(PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
+pub struct RefType {
+    pub nullable: bool,
+    pub heap_type: HeapType,
+}
+
+impl Debug for RefType {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("ref ")?;
+        if self.nullable {
+            f.write_str("null ")?;
+        }
+        self.heap_type.fmt(f)
+    }
+}
+
+impl RefType {
+    /// `funcref`, formerly `RefType::Func`.
+    pub const FUNC: RefType = RefType {
+        nullable: true,
+        heap_type: HeapType::Abstract(AbstractHeapType::Func),
+    };
+
+    /// `externref`, formerly `RefType::Extern`.
+    pub const EXTERN: RefType = RefType {
+        nullable: true,
+        heap_type: HeapType::Abstract(AbstractHeapType::Extern),
+    };
+}
+
+impl From<AbstractHeapType> for HeapType {
+    fn from(ty: AbstractHeapType) -> Self {
+        HeapType::Abstract(ty)
+    }
+}
+
+impl From<TypeId> for HeapType {
+    fn from(id: TypeId) -> Self {
+        HeapType::Concrete(id)
+    }
+}
+
+/// Converts shorthands such as `funcref` and `externref` to their full form.
+impl From<AbstractHeapType> for RefType {
+    fn from(ty: AbstractHeapType) -> Self {
+        RefType {
+            nullable: true,
+            heap_type: ty.into(),
+        }
+    }
+}
+
+impl Encode for RefType {
+    fn encode(&self, w: &mut impl crate::io::Write) -> Result<(), EncodeError> {
+        match self {
+            // Use the shorthand form for nullable abstract types like `funcref`.
+            RefType {
+                nullable: true,
+                heap_type: HeapType::Abstract(ty),
+            } => ty.encode(w),
+            RefType {
+                nullable,
+                heap_type,
+            } => {
+                match nullable {
+                    true => OP_CODE_REF_NULL,
+                    false => OP_CODE_REF,
+                }
+                .encode(w)?;
+                heap_type.encode(w)
+            }
+        }
+    }
+}
+
+impl DecodeWithDiscriminant for RefType {
+    const NAME: &'static str = "RefType";
+    type Discriminant = u8;
+
+    fn maybe_decode_with_discriminant(
+        discriminant: u8,
+        r: &mut impl crate::io::Read,
+    ) -> Result<Option<Self>, DecodeError> {
+        let nullable = match discriminant {
+            OP_CODE_REF | OP_CODE_REF_NULL => {
+                require_feature(r, Feature::FunctionReferences)?;
+                discriminant == OP_CODE_REF_NULL
+            }
+            _ => {
+                return Ok(
+                    AbstractHeapType::maybe_decode_with_discriminant(discriminant, r)?.map(|ty| {
+                        RefType {
+                            nullable: true,
+                            heap_type: HeapType::Abstract(ty),
+                        }
+                    }),
+                )
+            }
+        };
+        let heap_type =
+            HeapType::decode(r).map_err(|err| err.in_path(PathItem::Name("heap_type")))?;
+        Ok(Some(RefType {
+            nullable,
+            heap_type,
+        }))
+    }
+}
+
+impl Decode for RefType {
+    fn decode(r: &mut impl crate::io::Read) -> Result<Self, DecodeError> {
+        DecodeWithDiscriminant::decode_without_discriminant(r)
+    }
 }
 
-#[derive(Wasmbin, WasmbinCountable, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, WasmbinCountable, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct TableType {
     pub elem_type: RefType,
     pub limits: Limits,
 }
 
-#[derive(Wasmbin, Debug, Arbitrary, PartialEq, Eq, Hash, Clone, Visit)]
+#[derive(Wasmbin, Debug, PartialEq, Eq, Hash, Clone, Visit)]
+#[cfg_attr(feature = "arbitrary", derive(Arbitrary))]
 pub struct GlobalType {
     pub value_type: ValueType,
     pub mutable: bool,
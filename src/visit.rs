@@ -19,8 +19,8 @@ pub enum VisitError<E> {
     Custom(E),
 }
 
-impl<E: std::fmt::Display> std::fmt::Display for VisitError<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<E: core::fmt::Display> core::fmt::Display for VisitError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             VisitError::LazyDecode(err) => err.fmt(f),
             VisitError::Custom(err) => err.fmt(f),
@@ -28,8 +28,8 @@ impl<E: std::fmt::Display> std::fmt::Display for VisitError<E> {
     }
 }
 
-impl<E: std::fmt::Debug> std::fmt::Debug for VisitError<E> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl<E: core::fmt::Debug> core::fmt::Debug for VisitError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             VisitError::LazyDecode(err) => err.fmt(f),
             VisitError::Custom(err) => err.fmt(f),
@@ -37,10 +37,12 @@ impl<E: std::fmt::Debug> std::fmt::Debug for VisitError<E> {
     }
 }
 
-impl<E: std::error::Error> std::error::Error for VisitError<E> {}
+impl<E: core::error::Error> core::error::Error for VisitError<E> {}
 
 impl<E> VisitError<E> {
-    pub(crate) fn in_path(self, item: PathItem) -> Self {
+    #[doc(hidden)]
+    #[must_use]
+    pub fn in_path(self, item: PathItem) -> Self {
         #[allow(clippy::match_wildcard_for_single_variants)]
         match self {
             VisitError::LazyDecode(err) => VisitError::LazyDecode(err.in_path(item)),
@@ -53,7 +55,7 @@ impl<E> VisitError<E> {
 pub type NeverError = !;
 
 #[cfg(not(feature = "nightly"))]
-#[allow(clippy::empty_enum)]
+#[allow(clippy::empty_enums)]
 #[derive(Debug, thiserror::Error)]
 pub enum NeverError {}
 
@@ -69,12 +71,14 @@ impl From<VisitError<NeverError>> for DecodeError {
 pub trait VisitResult {
     type Error;
 
+    #[allow(clippy::wrong_self_convention)]
     fn as_result(self) -> Result<(), Self::Error>;
 }
 
 impl VisitResult for () {
     type Error = NeverError;
 
+    #[allow(clippy::wrong_self_convention)]
     fn as_result(self) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -83,6 +87,7 @@ impl VisitResult for () {
 impl VisitResult for bool {
     type Error = ();
 
+    #[allow(clippy::wrong_self_convention)]
     fn as_result(self) -> Result<(), Self::Error> {
         match self {
             true => Ok(()),
@@ -94,6 +99,7 @@ impl VisitResult for bool {
 impl<E> VisitResult for Result<(), E> {
     type Error = E;
 
+    #[allow(clippy::wrong_self_convention)]
     fn as_result(self) -> Result<(), Self::Error> {
         self
     }
@@ -119,7 +125,7 @@ pub trait Visit: 'static + Sized {
         &'a self,
         f: &mut F,
     ) -> Result<(), VisitError<E>> {
-        if let Some(v) = <dyn std::any::Any>::downcast_ref(self) {
+        if let Some(v) = <dyn core::any::Any>::downcast_ref(self) {
             f(v).map_err(VisitError::Custom)?;
         }
         self.visit_children(f)
@@ -129,7 +135,7 @@ pub trait Visit: 'static + Sized {
         &mut self,
         f: &mut F,
     ) -> Result<(), VisitError<E>> {
-        if let Some(v) = <dyn std::any::Any>::downcast_mut(self) {
+        if let Some(v) = <dyn core::any::Any>::downcast_mut(self) {
             f(v).map_err(VisitError::Custom)?;
         }
         self.visit_children_mut(f)
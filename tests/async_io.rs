@@ -0,0 +1,86 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "async")]
+
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use wasmbin::builtins::Blob;
+use wasmbin::indices::TypeId;
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeErrorKind, DecodeLimits, DecodeOptions, Limit};
+use wasmbin::sections::Section;
+use wasmbin::Module;
+
+mod common;
+
+use common::{assert_limit_exceeded, body, code_section};
+
+/// Runs a future that never has to wait, as in-memory readers and writers
+/// are always ready.
+fn block_on<F: Future>(future: F) -> F::Output {
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(std::ptr::null(), &VTABLE),
+        |_| {},
+        |_| {},
+        |_| {},
+    );
+    let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+    let mut future = pin!(future);
+    match future.as_mut().poll(&mut Context::from_waker(&waker)) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("in-memory I/O shouldn't be pending"),
+    }
+}
+
+fn module() -> Module {
+    common::module(vec![
+        Section::Function(Blob::from(vec![TypeId::from(0)])),
+        code_section(vec![body(vec![Instruction::Nop])]),
+    ])
+}
+
+#[test]
+fn round_trip() {
+    let module = module();
+    let encoded = block_on(module.encode_into_async(Vec::new())).unwrap();
+    assert_eq!(encoded, module.encode_into(Vec::new()).unwrap());
+    let decoded = block_on(Module::decode_from_async(encoded.as_slice())).unwrap();
+    assert_eq!(decoded, module);
+    assert_eq!(decoded, Module::decode_from(encoded.as_slice()).unwrap());
+}
+
+#[test]
+fn bogus_section_size_fails_before_allocating() {
+    let mut input = Module::default().encode_into(Vec::new()).unwrap();
+    input.extend_from_slice(&[0x0B, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    let err = block_on(Module::decode_from_async(input.as_slice())).unwrap_err();
+    assert!(matches!(err.kind, DecodeErrorKind::Io(_)));
+
+    let options = DecodeOptions {
+        limits: DecodeLimits {
+            max_allocated_bytes: 1 << 20,
+            ..DecodeLimits::default()
+        },
+        ..DecodeOptions::default()
+    };
+    assert_limit_exceeded(
+        block_on(Module::decode_with_options_async(
+            input.as_slice(),
+            &options,
+        )),
+        Limit::AllocatedBytes,
+    );
+}
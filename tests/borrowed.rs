@@ -0,0 +1,238 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::borrowed::{self, CustomSection, Export, NameSubSection};
+use wasmbin::builtins::{Blob, Lazy};
+use wasmbin::indices::{FuncId, LocalId, TypeId};
+use wasmbin::io::{DecodeError, DecodeErrorKind, DecodeLimits, DecodeOptions, Feature, Limit};
+use wasmbin::sections::{self, ExportDesc, ImportDesc, ImportPath, NameMap, Section};
+use wasmbin::types::{IndexType, Limits, MemType};
+use wasmbin::Module;
+
+mod common;
+
+use common::{assert_limit_exceeded, encode, names};
+
+fn input() -> Vec<u8> {
+    encode(vec![
+        Section::Import(Blob::from(vec![sections::Import {
+            path: ImportPath {
+                module: "env".to_owned(),
+                name: "log".to_owned(),
+            },
+            desc: ImportDesc::Func(TypeId::from(0)),
+        }])),
+        Section::Export(Blob::from(vec![sections::Export {
+            name: "main".to_owned(),
+            desc: ExportDesc::Func(FuncId::from(1)),
+        }])),
+        Section::Custom(Blob::from(sections::CustomSection::Name(Lazy::from(vec![
+            sections::NameSubSection::Module(Blob::from("test".to_owned())),
+            sections::NameSubSection::Func(Blob::from(names(vec![
+                (0, "log".to_owned()),
+                (1, "main".to_owned()),
+            ]))),
+            sections::NameSubSection::Local(Blob::from(names(vec![(
+                1,
+                names(vec![(0, "x".to_owned())]),
+            )]))),
+        ])))),
+    ])
+}
+
+fn points_into(input: &[u8], s: &str) -> bool {
+    input.as_ptr_range().contains(&s.as_ptr())
+}
+
+#[test]
+fn borrowed_names_point_into_input() {
+    let input = input();
+    let module = borrowed::Module::decode_from(&input).unwrap();
+
+    let imports: Vec<borrowed::Import> = module.sections[0].try_decode_borrowed().unwrap().unwrap();
+    assert_eq!(imports[0].path.module, "env");
+    assert!(points_into(&input, imports[0].path.module));
+    assert!(points_into(&input, imports[0].path.name));
+
+    let exports: Vec<Export> = module.sections[1].try_decode_borrowed().unwrap().unwrap();
+    assert_eq!(exports[0].name, "main");
+    assert!(points_into(&input, exports[0].name));
+
+    let custom: CustomSection = module.sections[2].try_decode_borrowed().unwrap().unwrap();
+    let name_section = custom.try_decode_names().unwrap().unwrap();
+    assert_eq!(
+        name_section,
+        [
+            NameSubSection::Module("test"),
+            NameSubSection::Func(names(vec![(0, "log"), (1, "main")])),
+            NameSubSection::Local(names::<FuncId, NameMap<LocalId, _>>(vec![(
+                1,
+                names(vec![(0, "x")])
+            )])),
+        ]
+    );
+    match &name_section[..] {
+        [NameSubSection::Module(module_name), NameSubSection::Func(funcs), NameSubSection::Local(locals)] =>
+        {
+            assert!(points_into(&input, module_name));
+            assert!(points_into(&input, funcs.items[1].value));
+            assert!(points_into(&input, locals.items[0].value.items[0].value));
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn borrowed_module_converts_to_owned() {
+    let input = input();
+    let module = borrowed::Module::decode_from(&input).unwrap();
+    let owned = module.to_owned_module();
+    assert_eq!(owned, Module::decode_from(input.as_slice()).unwrap());
+    assert_eq!(owned.encode_into(Vec::new()).unwrap(), input);
+}
+
+/// Decodes the module with the given options, and then some of its
+/// contents via `decode`.
+fn decode_with_options(
+    input: &[u8],
+    options: DecodeOptions,
+    decode: impl FnOnce(&borrowed::Module) -> Result<(), DecodeError>,
+) -> Result<(), DecodeError> {
+    decode(&borrowed::Module::decode_with_options(input, &options)?)
+}
+
+#[test]
+fn borrowed_decoding_applies_limits() {
+    let input = input();
+    let limits = |limits| DecodeOptions {
+        limits,
+        ..DecodeOptions::default()
+    };
+    assert_limit_exceeded(
+        decode_with_options(
+            &input,
+            limits(DecodeLimits {
+                max_sections: 2,
+                ..DecodeLimits::default()
+            }),
+            |_| Ok(()),
+        ),
+        Limit::Sections,
+    );
+    // Limits also apply to the contents decoded later.
+    assert_limit_exceeded(
+        decode_with_options(
+            &input,
+            limits(DecodeLimits {
+                max_vec_len: 0,
+                ..DecodeLimits::default()
+            }),
+            |module| {
+                module.sections[0]
+                    .try_decode_borrowed::<Vec<borrowed::Import>>()
+                    .unwrap()
+                    .map(drop)
+            },
+        ),
+        Limit::VecLen,
+    );
+    assert_limit_exceeded(
+        decode_with_options(
+            &input,
+            limits(DecodeLimits {
+                max_allocated_bytes: 1,
+                ..DecodeLimits::default()
+            }),
+            |module| {
+                module.sections[1]
+                    .try_decode_borrowed::<Vec<Export>>()
+                    .unwrap()
+                    .map(drop)
+            },
+        ),
+        Limit::AllocatedBytes,
+    );
+}
+
+#[test]
+fn borrowed_decoding_applies_features() {
+    let is_feature_not_enabled = |res: Result<(), DecodeError>| {
+        matches!(
+            res,
+            Err(DecodeError {
+                kind: DecodeErrorKind::FeatureNotEnabled(Feature::Memory64),
+                ..
+            })
+        )
+    };
+    let without_memory64 = || {
+        let mut options = DecodeOptions::default();
+        options.features.memory64 = false;
+        options
+    };
+    let mem = MemType {
+        is_shared: false,
+        index_type: IndexType::I64,
+        limits: Limits { min: 1, max: None },
+    };
+
+    let memory_section = encode(vec![Section::Memory(Blob::from(vec![mem.clone()]))]);
+    assert!(is_feature_not_enabled(decode_with_options(
+        &memory_section,
+        without_memory64(),
+        |module| module.sections[0]
+            .try_decode_owned::<sections::payload::Memory>()
+            .unwrap()
+            .map(drop),
+    )));
+
+    // Owned fields of borrowed values are checked too.
+    let import = encode(vec![Section::Import(Blob::from(vec![sections::Import {
+        path: ImportPath {
+            module: "env".to_owned(),
+            name: "memory".to_owned(),
+        },
+        desc: ImportDesc::Mem(mem),
+    }]))]);
+    assert!(is_feature_not_enabled(decode_with_options(
+        &import,
+        without_memory64(),
+        |module| module.sections[0]
+            .try_decode_borrowed::<Vec<borrowed::Import>>()
+            .unwrap()
+            .map(drop),
+    )));
+    decode_with_options(&import, DecodeOptions::default(), |module| {
+        module.sections[0]
+            .try_decode_borrowed::<Vec<borrowed::Import>>()
+            .unwrap()
+            .map(drop)
+    })
+    .unwrap();
+}
+
+#[test]
+fn borrowed_decoding_checks_section_features() {
+    let input = encode(vec![Section::Tag(Blob::from(vec![sections::Tag {
+        ty: TypeId::from(0),
+    }]))]);
+    borrowed::Module::decode_with_options(&input, &DecodeOptions::default()).unwrap();
+    let mut options = DecodeOptions::default();
+    options.features.exceptions = false;
+    let err = borrowed::Module::decode_with_options(&input, &options).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        DecodeErrorKind::FeatureNotEnabled(Feature::Exceptions)
+    ));
+}
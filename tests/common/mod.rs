@@ -0,0 +1,78 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Builders shared by the integration tests.
+
+// Each test only uses some of the builders.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+use wasmbin::builtins::Blob;
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeError, DecodeErrorKind, Limit};
+use wasmbin::sections::{FuncBody, Locals, NameAssoc, NameMap, Section};
+use wasmbin::types::ValueType;
+use wasmbin::Module;
+
+pub fn module(sections: Vec<Section>) -> Module {
+    Module {
+        sections,
+        ..Module::default()
+    }
+}
+
+pub fn encode(sections: Vec<Section>) -> Vec<u8> {
+    module(sections).encode_into(Vec::new()).unwrap()
+}
+
+pub fn names<I: From<u32>, V>(items: Vec<(u32, V)>) -> NameMap<I, V> {
+    NameMap {
+        items: items
+            .into_iter()
+            .map(|(index, value)| NameAssoc {
+                index: I::from(index),
+                value,
+            })
+            .collect(),
+    }
+}
+
+pub fn is_limit_exceeded(err: &DecodeError, expected: Limit) -> bool {
+    matches!(err.kind, DecodeErrorKind::LimitExceeded { limit, .. } if limit == expected)
+}
+
+pub fn assert_limit_exceeded<T: Debug>(res: Result<T, DecodeError>, expected: Limit) {
+    match res {
+        Err(err) if is_limit_exceeded(&err, expected) => {}
+        other => panic!("expected {:?} limit error, got {:?}", expected, other),
+    }
+}
+
+pub fn body(expr: Vec<Instruction>) -> Blob<FuncBody> {
+    Blob::from(FuncBody {
+        locals: vec![],
+        expr,
+    })
+}
+
+pub fn body_with_locals(repeat: u32, ty: ValueType, expr: Vec<Instruction>) -> Blob<FuncBody> {
+    Blob::from(FuncBody {
+        locals: vec![Locals { repeat, ty }],
+        expr,
+    })
+}
+
+pub fn code_section(bodies: Vec<Blob<FuncBody>>) -> Section {
+    Section::Code(Blob::from(bodies))
+}
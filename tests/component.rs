@@ -0,0 +1,83 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::component::{
+    ComponentSection, DefType, DefValType, ModuleOrComponent, PrimValType, ValType,
+};
+use wasmbin::io::{DecodeErrorKind, PathItem};
+use wasmbin::{decode_any, Component, Module};
+
+const COMPONENT: &[u8] = &[
+    // Preamble.
+    0x00, 0x61, 0x73, 0x6D, 0x0D, 0x00, 0x01, 0x00,
+    // Core module section with an empty module.
+    0x01, 0x08, 0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00,
+    // Type section with `list<u8>`.
+    0x07, 0x03, 0x01, 0x70, 0x7D,
+];
+
+#[test]
+fn component_round_trip() {
+    let component = Component::decode_from(COMPONENT).unwrap();
+    assert_eq!(component.sections.len(), 2);
+    match &component.sections[0] {
+        ComponentSection::CoreModule(module) => {
+            assert_eq!(*module.try_contents().unwrap(), Module::default());
+        }
+        other => panic!("unexpected section {:?}", other),
+    }
+    match &component.sections[1] {
+        ComponentSection::Type(types) => {
+            assert_eq!(
+                *types.try_contents().unwrap(),
+                [DefType::Value(DefValType::List(ValType::Prim(
+                    PrimValType::U8
+                )))]
+            );
+        }
+        other => panic!("unexpected section {:?}", other),
+    }
+    assert_eq!(component.encode_into(Vec::new()).unwrap(), COMPONENT);
+}
+
+#[test]
+fn decode_any_dispatches_on_preamble() {
+    match decode_any(COMPONENT).unwrap() {
+        ModuleOrComponent::Component(component) => {
+            assert_eq!(component, Component::decode_from(COMPONENT).unwrap());
+        }
+        ModuleOrComponent::Module(_) => panic!("expected a component"),
+    }
+
+    let module = Module::default().encode_into(Vec::new()).unwrap();
+    match decode_any(module.as_slice()).unwrap() {
+        ModuleOrComponent::Module(module) => assert_eq!(module, Module::default()),
+        ModuleOrComponent::Component(_) => panic!("expected a module"),
+    }
+}
+
+#[test]
+fn decode_any_reports_component_path() {
+    // Truncated type section.
+    let err = decode_any(&COMPONENT[..COMPONENT.len() - 1]).unwrap_err();
+    assert!(matches!(err.kind, DecodeErrorKind::Io(_)));
+    assert_eq!(
+        err.path().take(3).collect::<Vec<_>>(),
+        [
+            &PathItem::Variant("Component"),
+            &PathItem::Name("sections"),
+            &PathItem::Index(1),
+        ]
+    );
+}
@@ -0,0 +1,424 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::builtins::{Blob, Lazy, RawBlob};
+use wasmbin::indices::{
+    DataId, ElemId, FieldId, FuncId, GlobalId, LabelId, MemId, TableId, TagId, TypeId,
+};
+use wasmbin::instructions::Instruction;
+use wasmbin::io::Encode;
+use wasmbin::sections::code_metadata::{
+    BranchHint, CodeMetadataEntry, CodeMetadataError, CodeMetadataFunc,
+};
+use wasmbin::sections::dylink::{DylinkSubSection, ExportInfo, ImportInfo, MemInfo};
+use wasmbin::sections::linking::{
+    DataSymbolDef, InitFunc, Linking, LinkingSubSection, Reloc, RelocEntry, RelocType, SegmentInfo,
+    SymbolInfo, SymbolKind,
+};
+use wasmbin::sections::{
+    BuildId, CustomSection, FuncBody, ImportPath, NameMap, NameSubSection, PrefixedCustomSection,
+    Section, TargetFeature, TargetFeaturePrefix, UnknownNameSubSection,
+};
+use wasmbin::types::BlockType;
+use wasmbin::Module;
+
+mod common;
+
+use common::names;
+
+/// Encodes the section in a module and checks that it decodes back to the
+/// same value and encoding.
+fn round_trip(section: CustomSection) {
+    let module = common::module(vec![Section::Custom(Blob::from(section))]);
+    let encoded = module.encode_into(Vec::new()).unwrap();
+    let decoded = Module::decode_from(encoded.as_slice()).unwrap();
+    assert_eq!(decoded, module);
+    assert_eq!(decoded.encode_into(Vec::new()).unwrap(), encoded);
+}
+
+fn name<I: From<u32>>(index: u32, value: &str) -> NameMap<I, String> {
+    names(vec![(index, value.to_owned())])
+}
+
+#[test]
+fn extended_name_subsections_round_trip() {
+    let subsections = vec![
+        NameSubSection::Label(Blob::from(names::<FuncId, _>(vec![(
+            0,
+            name::<LabelId>(1, "loop"),
+        )]))),
+        NameSubSection::Type(Blob::from(name::<TypeId>(0, "point"))),
+        NameSubSection::Table(Blob::from(name::<TableId>(0, "table"))),
+        NameSubSection::Memory(Blob::from(name::<MemId>(0, "memory"))),
+        NameSubSection::Global(Blob::from(name::<GlobalId>(0, "sp"))),
+        NameSubSection::Elem(Blob::from(name::<ElemId>(0, "elem"))),
+        NameSubSection::Data(Blob::from(name::<DataId>(0, ".rodata"))),
+        NameSubSection::Field(Blob::from(names::<TypeId, _>(vec![(
+            0,
+            names::<FieldId, _>(vec![(0, "x".to_owned()), (1, "y".to_owned())]),
+        )]))),
+        NameSubSection::Tag(Blob::from(name::<TagId>(0, "error"))),
+        NameSubSection::Unknown(UnknownNameSubSection {
+            id: 12,
+            data: RawBlob {
+                contents: vec![0x01, 0x02],
+            },
+        }),
+    ];
+    round_trip(CustomSection::Name(Lazy::from(subsections)));
+}
+
+#[test]
+fn linking_round_trip() {
+    let symbols = vec![
+        SymbolInfo {
+            flags: 0,
+            kind: SymbolKind::Func {
+                index: FuncId::from(0),
+                name: Some("defined".to_owned()),
+            },
+        },
+        SymbolInfo {
+            flags: SymbolInfo::UNDEFINED,
+            kind: SymbolKind::Func {
+                index: FuncId::from(1),
+                name: None,
+            },
+        },
+        SymbolInfo {
+            flags: SymbolInfo::UNDEFINED | SymbolInfo::EXPLICIT_NAME,
+            kind: SymbolKind::Global {
+                index: GlobalId::from(0),
+                name: Some("imported".to_owned()),
+            },
+        },
+        SymbolInfo {
+            flags: SymbolInfo::BINDING_LOCAL,
+            kind: SymbolKind::Data {
+                name: "data".to_owned(),
+                def: Some(DataSymbolDef {
+                    segment: DataId::from(0),
+                    offset: 16,
+                    size: 4,
+                }),
+            },
+        },
+        SymbolInfo {
+            flags: SymbolInfo::UNDEFINED,
+            kind: SymbolKind::Data {
+                name: "extern_data".to_owned(),
+                def: None,
+            },
+        },
+        SymbolInfo {
+            flags: SymbolInfo::BINDING_LOCAL,
+            kind: SymbolKind::Section { section: 3 },
+        },
+    ];
+    round_trip(CustomSection::Linking(Lazy::from(Linking {
+        version: Linking::VERSION,
+        subsections: vec![
+            LinkingSubSection::SegmentInfo(Blob::from(vec![SegmentInfo {
+                name: ".data".to_owned(),
+                align: 2,
+                flags: 0,
+            }])),
+            LinkingSubSection::InitFuncs(Blob::from(vec![InitFunc {
+                priority: 65535,
+                symbol: 0,
+            }])),
+            LinkingSubSection::SymbolTable(Blob::from(symbols)),
+        ],
+    })));
+}
+
+#[test]
+fn reloc_round_trip() {
+    let section = PrefixedCustomSection::new(
+        "CODE",
+        Lazy::from(Reloc {
+            section: 3,
+            entries: vec![
+                RelocEntry {
+                    ty: RelocType::FunctionIndexLeb,
+                    offset: 4,
+                    index: 1,
+                    addend: 0,
+                },
+                RelocEntry {
+                    ty: RelocType::MemoryAddrSleb,
+                    offset: 10,
+                    index: 3,
+                    addend: -8,
+                },
+                RelocEntry {
+                    ty: RelocType::MemoryAddrI64,
+                    offset: 20,
+                    index: 3,
+                    addend: 1 << 40,
+                },
+            ],
+        }),
+    );
+    assert_eq!(section.name(), "reloc.CODE");
+    assert_eq!(section.suffix(), "CODE");
+    round_trip(CustomSection::Reloc(section));
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_sections_round_trip() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let seed: Vec<u8> = (0..65536_u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 11) as u8)
+        .collect();
+    let mut u = Unstructured::new(&seed);
+    let mut count = 0;
+    while !u.is_empty() {
+        // Sections that can't be encoded or decoded back, e.g. because of
+        // an invalid combination of linking flags, are out of scope here.
+        let Ok(section) = CustomSection::arbitrary(&mut u) else {
+            continue;
+        };
+        let module = common::module(vec![Section::Custom(Blob::from(section))]);
+        let encoded = module.encode_into(Vec::new()).unwrap();
+        let decoded = Module::decode_from(encoded.as_slice()).unwrap();
+        assert_eq!(decoded, module);
+        count += 1;
+    }
+    assert!(count > 0);
+}
+
+#[test]
+fn target_features_round_trip() {
+    round_trip(CustomSection::TargetFeatures(Lazy::from(vec![
+        TargetFeature {
+            prefix: TargetFeaturePrefix::Used,
+            name: "simd128".to_owned(),
+        },
+        TargetFeature {
+            prefix: TargetFeaturePrefix::Disallowed,
+            name: "atomics".to_owned(),
+        },
+        TargetFeature {
+            prefix: TargetFeaturePrefix::Required,
+            name: "bulk-memory".to_owned(),
+        },
+    ])));
+}
+
+#[test]
+fn dylink_round_trip() {
+    round_trip(CustomSection::Dylink0(Lazy::from(vec![
+        DylinkSubSection::MemInfo(Blob::from(MemInfo {
+            memory_size: 1024,
+            memory_align: 4,
+            table_size: 2,
+            table_align: 0,
+        })),
+        DylinkSubSection::Needed(Blob::from(vec!["libc.so".to_owned()])),
+        DylinkSubSection::ExportInfo(Blob::from(vec![ExportInfo {
+            name: "tls_var".to_owned(),
+            flags: 0x100,
+        }])),
+        DylinkSubSection::ImportInfo(Blob::from(vec![ImportInfo {
+            path: ImportPath {
+                module: "env".to_owned(),
+                name: "weak_fn".to_owned(),
+            },
+            flags: 0x1,
+        }])),
+    ])));
+}
+
+#[test]
+fn build_id_round_trip() {
+    round_trip(CustomSection::BuildId(Lazy::from(BuildId {
+        id: RawBlob {
+            contents: vec![0xDE, 0xAD, 0xBE, 0xEF],
+        },
+    })));
+}
+
+#[test]
+fn symbol_mismatching_flags_fails_to_encode() {
+    let section = CustomSection::Linking(Lazy::from(Linking {
+        version: Linking::VERSION,
+        subsections: vec![LinkingSubSection::SymbolTable(Blob::from(vec![
+            SymbolInfo {
+                flags: SymbolInfo::UNDEFINED,
+                kind: SymbolKind::Func {
+                    index: FuncId::from(0),
+                    name: Some("unexpected".to_owned()),
+                },
+            },
+        ]))],
+    }));
+    assert!(section.encode(&mut Vec::new()).is_err());
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_linking_entries_encode() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let seed: Vec<u8> = (0..4096_u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let mut u = Unstructured::new(&seed);
+    while !u.is_empty() {
+        let symbol = SymbolInfo::arbitrary(&mut u).unwrap();
+        symbol.encode(&mut Vec::new()).unwrap();
+        let entry = RelocEntry::arbitrary(&mut u).unwrap();
+        entry.encode(&mut Vec::new()).unwrap();
+    }
+}
+
+#[test]
+fn branch_hints_follow_modified_body() {
+    let mut body = Blob::from(FuncBody {
+        locals: vec![],
+        expr: vec![
+            Instruction::I32Const(1),
+            Instruction::IfStart(BlockType::Empty),
+            Instruction::Nop,
+            Instruction::End,
+        ],
+    });
+    let mut hints = CodeMetadataFunc {
+        func: FuncId::from(0),
+        entries: vec![CodeMetadataEntry {
+            offset: 3,
+            data: Blob::from(BranchHint::Likely),
+        }],
+    };
+    assert_eq!(hints.instruction_indices(&body).unwrap(), [1]);
+
+    // Offsets have to be updated manually after the body is modified.
+    let indices = hints.instruction_indices(&body).unwrap();
+    body.try_contents_mut()
+        .unwrap()
+        .expr
+        .insert(0, Instruction::Nop);
+    let indices: Vec<_> = indices.into_iter().map(|i| i + 1).collect();
+    hints.set_instruction_indices(&body, &indices).unwrap();
+    assert_eq!(hints.entries[0].offset, 4);
+    assert_eq!(hints.instruction_indices(&body).unwrap(), [2]);
+
+    assert!(matches!(
+        hints.set_instruction_indices(&body, &[]),
+        Err(CodeMetadataError::IndexCountMismatch {
+            expected: 1,
+            actual: 0
+        })
+    ));
+    assert!(matches!(
+        hints.set_instruction_indices(&body, &[10]),
+        Err(CodeMetadataError::InvalidIndex(10))
+    ));
+    assert_eq!(hints.entries[0].offset, 4);
+}
+
+#[test]
+fn module_keeps_code_metadata_of_modified_bodies() {
+    use wasmbin::sections::{payload, Import, ImportDesc};
+
+    let body = |expr| {
+        Blob::from(FuncBody {
+            locals: vec![],
+            expr,
+        })
+    };
+    let mut module = common::module(vec![
+        Section::Import(Blob::from(vec![Import {
+            path: ImportPath {
+                module: "env".to_owned(),
+                name: "f".to_owned(),
+            },
+            desc: ImportDesc::Func(TypeId::from(0)),
+        }])),
+        Section::Custom(Blob::from(CustomSection::BranchHint(Lazy::from(vec![
+            CodeMetadataFunc {
+                func: FuncId::from(2),
+                entries: vec![CodeMetadataEntry {
+                    offset: 3,
+                    data: Blob::from(BranchHint::Likely),
+                }],
+            },
+        ])))),
+        Section::Custom(Blob::from(CustomSection::CodeMetadata(
+            PrefixedCustomSection::new(
+                "custom",
+                Lazy::from(vec![CodeMetadataFunc {
+                    func: FuncId::from(2),
+                    entries: vec![CodeMetadataEntry {
+                        offset: 1,
+                        data: Blob::from(vec![42]),
+                    }],
+                }]),
+            ),
+        ))),
+        Section::Code(Blob::from(vec![
+            body(vec![Instruction::Nop, Instruction::End]),
+            body(vec![
+                Instruction::I32Const(1),
+                Instruction::IfStart(BlockType::Empty),
+                Instruction::Nop,
+                Instruction::End,
+            ]),
+        ])),
+    ]);
+
+    let mut seen = Vec::new();
+    module
+        .modify_func_bodies(|i, body, indices| {
+            seen.push((i, indices.to_vec()));
+            if i == 1 {
+                body.expr.insert(0, Instruction::Nop);
+                indices.iter_mut().for_each(|index| *index += 1);
+            }
+        })
+        .unwrap();
+    assert_eq!(seen, [(0, vec![]), (1, vec![1, 0])]);
+
+    let bodies = module
+        .find_std_section::<payload::Code>()
+        .unwrap()
+        .try_contents()
+        .unwrap();
+    let sections: Vec<_> = module
+        .sections
+        .iter()
+        .filter_map(|section| section.try_as::<payload::Custom>())
+        .map(|custom| custom.try_contents().unwrap())
+        .collect();
+    match sections[..] {
+        [CustomSection::BranchHint(hints), CustomSection::CodeMetadata(metadata)] => {
+            let hints = &hints.try_contents().unwrap()[0];
+            assert_eq!(hints.entries[0].offset, 4);
+            assert_eq!(hints.instruction_indices(&bodies[1]).unwrap(), [2]);
+            let metadata = &metadata.data.try_contents().unwrap()[0];
+            assert_eq!(metadata.entries[0].offset, 2);
+            assert_eq!(metadata.instruction_indices(&bodies[1]).unwrap(), [1]);
+        }
+        _ => unreachable!(),
+    }
+
+    // Invalid indices leave the metadata intact.
+    assert!(matches!(
+        module.modify_func_bodies(|_, _, indices| indices.iter_mut().for_each(|index| *index = 10)),
+        Err(CodeMetadataError::InvalidIndex(10))
+    ));
+}
@@ -0,0 +1,485 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::builtins::{Blob, RawBlob};
+use wasmbin::indices::TypeId;
+use wasmbin::instructions::{Instruction, MemArg};
+use wasmbin::io::{
+    DecodeError, DecodeErrorKind, DecodeLimits, DecodeOptions, Feature, Features, Limit, PathItem,
+};
+use wasmbin::sections::{Data, DataInit, FuncBody, Locals, Section};
+use wasmbin::types::{
+    AbstractHeapType, BlockType, CompositeType, FuncType, IndexType, Limits, MemType, RecGroup,
+    RefType, SubType, ValueType,
+};
+use wasmbin::visit::Visit;
+use wasmbin::Module;
+
+mod common;
+
+use common::{assert_limit_exceeded, body, code_section, encode};
+
+fn decode_with_limits(input: &[u8], limits: DecodeLimits) -> Result<Module, DecodeError> {
+    let options = DecodeOptions {
+        limits,
+        ..DecodeOptions::default()
+    };
+    Module::decode_with_options(input, &options)
+}
+
+/// Decodes the module and all of its lazy contents with the given limits.
+fn decode_fully(input: &[u8], limits: DecodeLimits) -> Result<Module, DecodeError> {
+    let module = decode_with_limits(input, limits)?;
+    module.visit(|()| {})?;
+    Ok(module)
+}
+
+#[test]
+fn max_sections() {
+    let input = encode(vec![
+        Section::Function(Blob::from(vec![])),
+        Section::Code(Blob::from(vec![])),
+    ]);
+    let limits = DecodeLimits {
+        max_sections: 2,
+        ..DecodeLimits::default()
+    };
+    decode_fully(&input, limits.clone()).unwrap();
+    assert_limit_exceeded(
+        decode_fully(
+            &input,
+            DecodeLimits {
+                max_sections: 1,
+                ..limits
+            },
+        ),
+        Limit::Sections,
+    );
+}
+
+#[test]
+fn max_vec_len() {
+    let input = encode(vec![Section::Function(Blob::from(vec![
+        TypeId::from(0),
+        TypeId::from(1),
+    ]))]);
+    decode_fully(
+        &input,
+        DecodeLimits {
+            max_vec_len: 2,
+            ..DecodeLimits::default()
+        },
+    )
+    .unwrap();
+    assert_limit_exceeded(
+        decode_fully(
+            &input,
+            DecodeLimits {
+                max_vec_len: 1,
+                ..DecodeLimits::default()
+            },
+        ),
+        Limit::VecLen,
+    );
+}
+
+#[test]
+fn max_locals() {
+    let input = encode(vec![code_section(vec![Blob::from(FuncBody {
+        locals: vec![
+            Locals {
+                repeat: 2,
+                ty: ValueType::I32,
+            },
+            Locals {
+                repeat: 3,
+                ty: ValueType::I64,
+            },
+        ],
+        expr: vec![],
+    })])]);
+    let limits = DecodeLimits {
+        max_locals: 4,
+        ..DecodeLimits::default()
+    };
+    // Function bodies are decoded lazily, but still with the same limits.
+    let module = decode_with_limits(&input, limits.clone()).unwrap();
+    assert_limit_exceeded(module.visit(|()| {}).map_err(Into::into), Limit::Locals);
+    assert_limit_exceeded(decode_fully(&input, limits), Limit::Locals);
+    decode_fully(
+        &input,
+        DecodeLimits {
+            max_locals: 5,
+            ..DecodeLimits::default()
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn max_instructions() {
+    let input = encode(vec![code_section(vec![body(vec![Instruction::Nop; 3])])]);
+    decode_fully(
+        &input,
+        DecodeLimits {
+            max_instructions: 3,
+            ..DecodeLimits::default()
+        },
+    )
+    .unwrap();
+    assert_limit_exceeded(
+        decode_fully(
+            &input,
+            DecodeLimits {
+                max_instructions: 2,
+                ..DecodeLimits::default()
+            },
+        ),
+        Limit::Instructions,
+    );
+}
+
+#[test]
+fn max_nesting_depth() {
+    let input = encode(vec![code_section(vec![body(vec![
+        Instruction::BlockStart(BlockType::Empty),
+        Instruction::LoopStart(BlockType::Empty),
+        Instruction::End,
+        Instruction::End,
+    ])])]);
+    decode_fully(
+        &input,
+        DecodeLimits {
+            max_nesting_depth: 2,
+            ..DecodeLimits::default()
+        },
+    )
+    .unwrap();
+    assert_limit_exceeded(
+        decode_fully(
+            &input,
+            DecodeLimits {
+                max_nesting_depth: 1,
+                ..DecodeLimits::default()
+            },
+        ),
+        Limit::NestingDepth,
+    );
+}
+
+#[test]
+fn max_allocated_bytes() {
+    let input = encode(vec![Section::Data(Blob::from(vec![Data {
+        init: DataInit::Passive,
+        blob: RawBlob {
+            contents: vec![0; 1000],
+        },
+    }]))]);
+    decode_fully(&input, DecodeLimits::default()).unwrap();
+    assert_limit_exceeded(
+        decode_fully(
+            &input,
+            DecodeLimits {
+                max_allocated_bytes: 1000,
+                ..DecodeLimits::default()
+            },
+        ),
+        Limit::AllocatedBytes,
+    );
+}
+
+#[test]
+fn max_allocated_bytes_before_reading() {
+    // Section claiming to be much larger than the actual input.
+    let mut input = encode(vec![]);
+    input.extend_from_slice(&[0x0B, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+    assert_limit_exceeded(
+        decode_with_limits(
+            &input,
+            DecodeLimits {
+                max_allocated_bytes: 1 << 20,
+                ..DecodeLimits::default()
+            },
+        ),
+        Limit::AllocatedBytes,
+    );
+}
+
+fn decode_with_features(input: &[u8], features: Features) -> Result<Module, DecodeError> {
+    let options = DecodeOptions {
+        features,
+        ..DecodeOptions::default()
+    };
+    let module = Module::decode_with_options(input, &options)?;
+    module.visit(|()| {})?;
+    Ok(module)
+}
+
+/// Checks that `input` decodes with all features enabled, but not with
+/// `disable` applied, and that the error names the rejected variant.
+fn assert_feature_gated(
+    input: &[u8],
+    disable: impl FnOnce(&mut Features),
+    feature: Feature,
+    variant: &'static str,
+) {
+    decode_with_features(input, Features::default()).unwrap();
+    let mut features = Features::default();
+    disable(&mut features);
+    let err = decode_with_features(input, features).unwrap_err();
+    assert!(
+        matches!(err.kind, DecodeErrorKind::FeatureNotEnabled(f) if f == feature),
+        "expected {} to be rejected, got {}",
+        feature,
+        err
+    );
+    assert!(
+        err.path().any(|item| *item == PathItem::Variant(variant)),
+        "expected {} in the path of {}",
+        variant,
+        err
+    );
+}
+
+fn instruction_module(instruction: Instruction) -> Vec<u8> {
+    encode(vec![code_section(vec![body(vec![instruction])])])
+}
+
+#[test]
+fn mvp_decodes_with_all_features_disabled() {
+    let features = Features {
+        tail_call: false,
+        simd: false,
+        relaxed_simd: false,
+        threads: false,
+        exceptions: false,
+        memory64: false,
+        function_references: false,
+        gc: false,
+        component_model: false,
+    };
+    decode_with_features(&instruction_module(Instruction::Nop), features).unwrap();
+}
+
+#[test]
+fn shared_memory_requires_threads() {
+    // Shared memories are understood even without the `threads` cargo
+    // feature, and only rejected at runtime.
+    let input = encode(vec![Section::Memory(Blob::from(vec![MemType {
+        is_shared: true,
+        index_type: IndexType::I32,
+        limits: Limits {
+            min: 1,
+            max: Some(2),
+        },
+    }]))]);
+    assert_feature_gated(
+        &input,
+        |f| f.threads = false,
+        Feature::Threads,
+        "MemTypeRepr::SharedMinMax",
+    );
+    let module = decode_with_features(&input, Features::default()).unwrap();
+    assert_eq!(module.encode_into(Vec::new()).unwrap(), input);
+}
+
+#[test]
+fn return_call_requires_tail_call() {
+    assert_feature_gated(
+        &instruction_module(Instruction::ReturnCall(wasmbin::indices::FuncId::from(0))),
+        |f| f.tail_call = false,
+        Feature::TailCall,
+        "Instruction::ReturnCall",
+    );
+}
+
+#[test]
+fn simd_instruction_requires_simd() {
+    use wasmbin::instructions::simd::SIMD;
+
+    assert_feature_gated(
+        &instruction_module(Instruction::SIMD(SIMD::I8x16Swizzle)),
+        |f| f.simd = false,
+        Feature::Simd,
+        "Instruction::SIMD",
+    );
+}
+
+#[test]
+fn relaxed_simd_instruction_requires_relaxed_simd() {
+    use wasmbin::instructions::simd::SIMD;
+
+    let input = instruction_module(Instruction::SIMD(SIMD::I8x16RelaxedSwizzle));
+    assert_feature_gated(
+        &input,
+        |f| f.relaxed_simd = false,
+        Feature::RelaxedSimd,
+        "SIMD::I8x16RelaxedSwizzle",
+    );
+    // Regular SIMD instructions are still accepted.
+    decode_with_features(
+        &instruction_module(Instruction::SIMD(SIMD::I8x16Swizzle)),
+        Features {
+            relaxed_simd: false,
+            ..Features::default()
+        },
+    )
+    .unwrap();
+}
+
+#[test]
+fn exceptions_require_exceptions() {
+    use wasmbin::sections::Tag;
+
+    assert_feature_gated(
+        &instruction_module(Instruction::ThrowRef),
+        |f| f.exceptions = false,
+        Feature::Exceptions,
+        "Instruction::ThrowRef",
+    );
+    assert_feature_gated(
+        &encode(vec![Section::Tag(Blob::from(vec![Tag {
+            ty: TypeId::from(0),
+        }]))]),
+        |f| f.exceptions = false,
+        Feature::Exceptions,
+        "Section::Tag",
+    );
+}
+
+#[test]
+fn memory64_requires_memory64() {
+    assert_feature_gated(
+        &encode(vec![Section::Memory(Blob::from(vec![MemType {
+            is_shared: false,
+            index_type: IndexType::I64,
+            limits: Limits { min: 1, max: None },
+        }]))]),
+        |f| f.memory64 = false,
+        Feature::Memory64,
+        "MemTypeRepr::Min64",
+    );
+}
+
+#[test]
+fn memory64_offset_requires_memory64() {
+    let load = |offset| {
+        instruction_module(Instruction::I32Load(MemArg {
+            align: 2,
+            memory: None,
+            offset,
+        }))
+    };
+    let features = Features {
+        memory64: false,
+        ..Features::default()
+    };
+    decode_with_features(&load(u32::MAX.into()), features).unwrap();
+    assert_feature_gated(
+        &load(u64::from(u32::MAX) + 1),
+        |f| f.memory64 = false,
+        Feature::Memory64,
+        "Instruction::I32Load",
+    );
+}
+
+#[test]
+fn ref_as_non_null_requires_function_references() {
+    assert_feature_gated(
+        &instruction_module(Instruction::RefAsNonNull),
+        |f| f.function_references = false,
+        Feature::FunctionReferences,
+        "Instruction::RefAsNonNull",
+    );
+}
+
+#[test]
+fn concrete_heap_types_require_function_references() {
+    assert_eq!(RefType::from(AbstractHeapType::Func), RefType::FUNC);
+    let features = Features {
+        function_references: false,
+        ..Features::default()
+    };
+    decode_with_features(
+        &instruction_module(Instruction::RefNull(AbstractHeapType::Func.into())),
+        features,
+    )
+    .unwrap();
+    assert_feature_gated(
+        &instruction_module(Instruction::RefNull(TypeId::from(0).into())),
+        |f| f.function_references = false,
+        Feature::FunctionReferences,
+        "HeapType::Concrete",
+    );
+}
+
+#[test]
+fn ref_eq_requires_gc() {
+    assert_feature_gated(
+        &instruction_module(Instruction::RefEq),
+        |f| f.gc = false,
+        Feature::Gc,
+        "Instruction::RefEq",
+    );
+}
+
+#[test]
+fn struct_types_require_gc() {
+    let features = Features {
+        gc: false,
+        ..Features::default()
+    };
+    let func = FuncType {
+        params: vec![ValueType::I32],
+        results: vec![],
+    };
+    decode_with_features(
+        &encode(vec![Section::Type(Blob::from(vec![RecGroup::from(func)]))]),
+        features,
+    )
+    .unwrap();
+    assert_feature_gated(
+        &encode(vec![Section::Type(Blob::from(vec![RecGroup::from(
+            SubType::from(CompositeType::Struct(vec![])),
+        )]))]),
+        |f| f.gc = false,
+        Feature::Gc,
+        "CompositeType::Struct",
+    );
+}
+
+#[test]
+fn component_requires_component_model() {
+    use wasmbin::decode_any_with_options;
+
+    let component = wasmbin::Component::default()
+        .encode_into(Vec::new())
+        .unwrap();
+    decode_any_with_options(component.as_slice(), &DecodeOptions::default()).unwrap();
+    let options = DecodeOptions {
+        features: Features {
+            component_model: false,
+            ..Features::default()
+        },
+        ..DecodeOptions::default()
+    };
+    let err = decode_any_with_options(component.as_slice(), &options).unwrap_err();
+    assert!(matches!(
+        err.kind,
+        DecodeErrorKind::FeatureNotEnabled(Feature::ComponentModel)
+    ));
+    // Core modules are still accepted.
+    let module = Module::default().encode_into(Vec::new()).unwrap();
+    decode_any_with_options(module.as_slice(), &options).unwrap();
+}
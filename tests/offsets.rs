@@ -0,0 +1,80 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::builtins::Blob;
+use wasmbin::indices::TypeId;
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeError, DecodeErrorKind, PathItem};
+use wasmbin::sections::Section;
+use wasmbin::visit::Visit;
+use wasmbin::Module;
+
+mod common;
+
+use common::{body, encode};
+
+fn input() -> Vec<u8> {
+    encode(vec![
+        Section::Function(Blob::from(vec![TypeId::from(0), TypeId::from(0)])),
+        Section::Code(Blob::from(vec![
+            body(vec![Instruction::Nop]),
+            body(vec![
+                Instruction::Nop,
+                Instruction::I32Const(0),
+                Instruction::Drop,
+            ]),
+        ])),
+    ])
+}
+
+#[test]
+fn lazy_error_points_to_failed_instruction() {
+    let mut input = input();
+    let (_, offsets) = Module::decode_with_offsets(input.as_slice()).unwrap();
+    let drop_offset = offsets.func_bodies[1].instructions[2].start;
+    assert_eq!(input[drop_offset], 0x1A);
+    // Replace `drop` with an unknown opcode.
+    input[drop_offset] = 0xFF;
+
+    // Function bodies are only decoded on demand, but the offset is still
+    // relative to the whole input.
+    for module in [
+        Module::decode_from(input.as_slice()).unwrap(),
+        Module::decode_from_slice(&input).unwrap(),
+    ] {
+        let err: DecodeError = module.visit(|()| {}).unwrap_err().into();
+        assert!(matches!(
+            err.kind,
+            DecodeErrorKind::UnsupportedDiscriminant { .. }
+        ));
+        assert_eq!(err.offset(), Some(drop_offset));
+        assert_eq!(err.path().last(), Some(&PathItem::Index(2)));
+    }
+}
+
+#[test]
+fn eager_error_points_to_failed_section() {
+    let input = input();
+    let (_, offsets) = Module::decode_with_offsets(input.as_slice()).unwrap();
+    // Cut the input in the middle of the Code section.
+    let code_range = offsets.sections[1].range.clone();
+    let input = &input[..code_range.end - 1];
+    for err in [
+        Module::decode_from(input).unwrap_err(),
+        Module::decode_from_slice(input).unwrap_err(),
+    ] {
+        assert!(matches!(err.kind, DecodeErrorKind::Io(_)));
+        assert_eq!(err.offset(), Some(code_range.start));
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "rayon")]
+
+use wasmbin::builtins::Blob;
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeError, DecodeErrorKind, DecodeLimits, DecodeOptions, Limit, PathItem};
+use wasmbin::sections::FuncBody;
+use wasmbin::types::ValueType;
+use wasmbin::visit::Visit;
+use wasmbin::Module;
+
+mod common;
+
+use common::{body_with_locals, code_section, encode};
+
+fn body(locals: u32) -> Blob<FuncBody> {
+    body_with_locals(locals, ValueType::I32, vec![Instruction::Nop])
+}
+
+#[test]
+fn workers_apply_options_and_report_body_index() {
+    let input = encode(vec![code_section(vec![
+        body(1),
+        body(2),
+        body(10),
+        body(3),
+    ])]);
+    let options = DecodeOptions {
+        limits: DecodeLimits {
+            max_locals: 5,
+            ..DecodeLimits::default()
+        },
+        ..DecodeOptions::default()
+    };
+
+    let module = Module::decode_with_options(input.as_slice(), &options).unwrap();
+    let err = module.par_decode_func_bodies().unwrap_err();
+    assert!(matches!(
+        err.kind,
+        DecodeErrorKind::LimitExceeded {
+            limit: Limit::Locals,
+            max: 5
+        }
+    ));
+    let path: Vec<_> = err.path().collect();
+    assert_eq!(
+        path[..7],
+        [
+            &PathItem::Variant("Module"),
+            &PathItem::Name("sections"),
+            &PathItem::Index(0),
+            &PathItem::Variant("Section::Code"),
+            &PathItem::Variant("Blob"),
+            &PathItem::Name("contents"),
+            &PathItem::Index(2),
+        ]
+    );
+
+    // Same as the error produced by sequential decoding.
+    let sequential = Module::decode_with_options(input.as_slice(), &options)
+        .unwrap()
+        .visit(|()| {})
+        .unwrap_err();
+    let sequential: DecodeError = sequential.into();
+    assert_eq!(path, sequential.path().collect::<Vec<_>>());
+
+    // Without options, all bodies are fine.
+    Module::decode_from(input.as_slice())
+        .unwrap()
+        .par_decode_func_bodies()
+        .unwrap();
+}
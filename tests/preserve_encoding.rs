@@ -0,0 +1,162 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![cfg(feature = "std")]
+
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeOptions, Encode};
+use wasmbin::sections::payload;
+use wasmbin::visit::Visit;
+use wasmbin::Module;
+
+const HEADER: [u8; 8] = [0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00];
+
+/// Encodes `value` as an unsigned LEB128 padded to `width` bytes.
+fn leb(value: usize, width: usize) -> Vec<u8> {
+    (0..width)
+        .map(|i| {
+            let byte = (value >> (7 * i)) as u8 & 0x7F;
+            if i + 1 < width {
+                byte | 0x80
+            } else {
+                byte
+            }
+        })
+        .collect()
+}
+
+fn sized(contents: &[u8], width: usize) -> Vec<u8> {
+    let mut res = leb(contents.len(), width);
+    res.extend_from_slice(contents);
+    res
+}
+
+fn section(id: u8, contents: &[u8], width: usize) -> Vec<u8> {
+    let mut res = vec![id];
+    res.extend(sized(contents, width));
+    res
+}
+
+/// Body with padded LEB128s in the locals, the immediate and its size.
+fn padded_body() -> Vec<u8> {
+    sized(
+        &[
+            // One group of two i32 locals.
+            0x81, 0x00, 0x82, 0x00, 0x7F, //
+            // i32.const 1
+            0x41, 0x81, 0x80, 0x80, 0x00, //
+            0x0B,
+        ],
+        2,
+    )
+}
+
+/// Body with `i32.const -1` encoded in two bytes instead of one.
+fn second_body(width: usize) -> Vec<u8> {
+    sized(&[0x00, 0x41, 0xFF, 0x7F, 0x0B], width)
+}
+
+/// Module up to the Code section.
+fn signatures() -> Vec<u8> {
+    let mut input = HEADER.to_vec();
+    // One `() -> i32` type.
+    input.extend(section(
+        1,
+        &[0x81, 0x00, 0x60, 0x80, 0x00, 0x81, 0x00, 0x7F],
+        5,
+    ));
+    // Two functions of type 0.
+    input.extend(section(3, &[0x82, 0x00, 0x80, 0x80, 0x00, 0x00], 2));
+    input
+}
+
+fn input() -> Vec<u8> {
+    let mut input = signatures();
+    let mut code = leb(2, 2);
+    code.extend(padded_body());
+    code.extend(second_body(3));
+    input.extend(section(10, &code, 3));
+    input
+}
+
+fn decode_preserved(input: &[u8]) -> Module {
+    let options = DecodeOptions {
+        preserve_encoding: true,
+        ..DecodeOptions::default()
+    };
+    let module = Module::decode_with_options(input, &options).unwrap();
+    // Immutable access keeps the contents unmodified.
+    module.visit(|()| {}).unwrap();
+    module
+}
+
+#[test]
+fn over_long_lebs_round_trip() {
+    let input = input();
+    let module = decode_preserved(&input);
+    assert_eq!(module.encode_into(Vec::new()).unwrap(), input);
+
+    // Without the option, size prefixes are re-encoded canonically.
+    let module2 = Module::decode_from(input.as_slice()).unwrap();
+    assert_eq!(module2, module);
+    let out = module2.encode_into(Vec::new()).unwrap();
+    assert_ne!(out, input);
+    assert_eq!(Module::decode_from(out.as_slice()).unwrap(), module);
+}
+
+#[test]
+fn unchanged_contents_round_trip_after_mutable_access() {
+    let input = input();
+    let mut module = decode_preserved(&input);
+    module.visit_mut(|()| {}).unwrap();
+    assert_eq!(module.encode_into(Vec::new()).unwrap(), input);
+
+    // Reverting a modification brings the original bytes back as well.
+    let expr = &mut module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()
+        .unwrap()[0]
+        .try_contents_mut()
+        .unwrap()
+        .expr;
+    expr.push(Instruction::Nop);
+    expr.pop();
+    assert_eq!(module.encoded_len().unwrap(), input.len());
+    assert_eq!(module.encode_into(Vec::new()).unwrap(), input);
+}
+
+#[test]
+fn modified_blob_is_reencoded_canonically() {
+    let input = input();
+    let mut module = decode_preserved(&input);
+    let bodies = module
+        .find_std_section_mut::<payload::Code>()
+        .unwrap()
+        .try_contents_mut()
+        .unwrap();
+    bodies[1]
+        .try_contents_mut()
+        .unwrap()
+        .expr
+        .insert(0, Instruction::Nop);
+
+    let mut expected = signatures();
+    let mut code = vec![0x02];
+    // The untouched body is still written back as it was read.
+    code.extend(padded_body());
+    code.extend(sized(&[0x00, 0x01, 0x41, 0x7F, 0x0B], 1));
+    expected.extend(section(10, &code, 1));
+    assert_eq!(module.encode_into(Vec::new()).unwrap(), expected);
+}
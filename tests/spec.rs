@@ -18,7 +18,7 @@ use libtest_mimic::{run_tests, Arguments, Outcome, Test};
 use std::fs::{read_dir, read_to_string};
 use std::path::Path;
 use wasmbin::{
-    io::DecodeError,
+    io::{DecodeError, DecodeOptions},
     visit::{Visit, VisitError},
     Module,
 };
@@ -55,8 +55,8 @@ const IGNORED_MODULES: &[&[u8]] = &[
         0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, 0x0B, 0x06, 0x01, 0x01, 0x41, 0x00, 0x0B,
         0x00,
     ],
-    // Upstream malformed test that becomes well-formed if threads are enabled.
-    #[cfg(feature = "threads")]
+    // Upstream malformed test that is well-formed with threads, whose shared
+    // memories are decoded regardless of the cargo feature.
     &[
         0x00, 0x61, 0x73, 0x6D, 0x01, 0x00, 0x00, 0x00, 0x05, 0x03, 0x01, 0x02, 0x00,
     ],
@@ -131,7 +131,7 @@ fn read_tests_from_file(path: &Path, dest: &mut Vec<Test<WasmTest>>) {
 fn read_tests_from_dir(path: &Path, dest: &mut Vec<Test<WasmTest>>) {
     for file in read_dir(path)? {
         let path = file?.path();
-        if path.extension().map_or(false, |ext| ext == "wast") {
+        if path.extension().is_some_and(|ext| ext == "wast") {
             read_tests_from_file(&path, dest)?;
         }
     }
@@ -142,18 +142,19 @@ fn read_all_tests(path: &Path) -> Vec<Test<WasmTest>> {
     let mut tests = Vec::new();
     let proposals_dir = path.join("proposals");
 
-    macro_rules! read_proposal_tests {
-        ($name:literal) => {
-            if cfg!(feature = $name) {
-                read_tests_from_dir(&proposals_dir.join($name), &mut tests).context($name)?
-            }
-        };
+    for dir in [
+        "tail-call",
+        "simd",
+        "relaxed-simd",
+        "threads",
+        "exception-handling",
+        "memory64",
+        "function-references",
+        "gc",
+    ] {
+        read_tests_from_dir(&proposals_dir.join(dir), &mut tests).context(dir)?;
     }
 
-    read_proposal_tests!("tail-call");
-    read_proposal_tests!("simd");
-    read_proposal_tests!("threads");
-
     read_tests_from_dir(path, &mut tests)?;
 
     if tests.is_empty() {
@@ -163,6 +164,15 @@ fn read_all_tests(path: &Path) -> Vec<Test<WasmTest>> {
     tests
 }
 
+/// Reads our own tests for proposals that upstream tests don't cover in a
+/// form our version of `wast` can parse.
+#[throws]
+fn read_local_tests(path: &Path, dest: &mut Vec<Test<WasmTest>>) {
+    for name in ["exceptions", "memory64", "function-references"] {
+        read_tests_from_file(&path.join(name).with_extension("wast"), dest).context(name)?;
+    }
+}
+
 fn unlazify<T: Visit>(mut wasm: T) -> Result<T, DecodeError> {
     match wasm.visit_mut(|()| {}) {
         Ok(()) => Ok(wasm),
@@ -200,11 +210,29 @@ fn run_test(test: &WasmTest) {
             );
         }
     }
+    // When asked to preserve the original encoding, the output should
+    // match the input exactly, even after the contents were accessed
+    // mutably.
+    let options = DecodeOptions {
+        preserve_encoding: true,
+        ..DecodeOptions::default()
+    };
+    let module =
+        Module::decode_with_options(test.module.as_slice(), &options).and_then(unlazify)?;
+    let out = module.encode_into(Vec::new())?;
+    if out != test.module {
+        bail!(
+            "Preserved roundtrip mismatch. Old: {:02X?}\nNew: {:02X?}",
+            test.module,
+            out
+        );
+    }
 }
 
 #[throws]
 fn main() {
-    let tests = read_all_tests(&Path::new("tests").join("testsuite"))?;
+    let mut tests = read_all_tests(&Path::new("tests").join("testsuite"))?;
+    read_local_tests(&Path::new("tests").join("wast"), &mut tests)?;
 
     run_tests(&Arguments::from_args(), tests, |test| {
         match run_test(&test.data) {
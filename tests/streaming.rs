@@ -0,0 +1,175 @@
+// Copyright 2020 Google Inc. All Rights Reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use wasmbin::builtins::Blob;
+use wasmbin::indices::{FuncId, TypeId};
+use wasmbin::instructions::Instruction;
+use wasmbin::io::{DecodeLimits, DecodeOptions, Limit};
+use wasmbin::sections::{CustomSection, Export, ExportDesc, RawCustomSection, Section};
+use wasmbin::streaming::{Decoder, Event};
+use wasmbin::types::ValueType;
+use wasmbin::Module;
+
+mod common;
+
+use common::{body_with_locals, encode, is_limit_exceeded};
+
+fn input() -> Vec<u8> {
+    let body = |n: u32| {
+        body_with_locals(
+            n,
+            ValueType::I64,
+            (0..n).map(|_| Instruction::Nop).collect(),
+        )
+    };
+    encode(vec![
+        Section::Function(Blob::from(vec![TypeId::from(0); 3])),
+        Section::Export(Blob::from(vec![Export {
+            name: "main".to_owned(),
+            desc: ExportDesc::Func(FuncId::from(0)),
+        }])),
+        // Large enough to need a multi-byte size.
+        Section::Code(Blob::from(vec![body(0), body(200), body(3)])),
+        Section::Custom(Blob::from(CustomSection::Other(RawCustomSection {
+            name: "extra".to_owned(),
+            data: vec![0xAB; 300],
+        }))),
+    ])
+}
+
+/// Events expected for a module, based on non-streaming decoding.
+fn expected_events(input: &[u8]) -> Vec<Event> {
+    let module = Module::decode_from(input).unwrap();
+    let mut events = Vec::new();
+    for section in module.sections {
+        match section {
+            Section::Code(code) => {
+                let bodies = code.try_into_contents().unwrap();
+                events.push(Event::CodeSectionStart {
+                    count: bodies.len(),
+                });
+                events.extend(bodies.into_iter().map(Event::FuncBody));
+            }
+            section => events.push(Event::Section(section)),
+        }
+    }
+    events
+}
+
+/// Feeds the input in chunks of the given sizes, repeated as needed.
+fn decode_in_chunks(input: &[u8], chunk_sizes: impl Iterator<Item = usize>) -> Vec<Event> {
+    let mut decoder = Decoder::new();
+    let mut events = Vec::new();
+    let mut rest = input;
+    for size in chunk_sizes {
+        if rest.is_empty() {
+            break;
+        }
+        let (chunk, tail) = rest.split_at(size.min(rest.len()));
+        rest = tail;
+        decoder.push(chunk);
+        while let Some(event) = decoder.next_event().unwrap() {
+            events.push(event);
+        }
+    }
+    assert!(rest.is_empty());
+    decoder.finish().unwrap();
+    events
+}
+
+#[test]
+fn byte_by_byte() {
+    let input = input();
+    assert_eq!(
+        decode_in_chunks(&input, std::iter::repeat(1)),
+        expected_events(&input)
+    );
+}
+
+#[test]
+fn arbitrary_chunks() {
+    let input = input();
+    let expected = expected_events(&input);
+    for seed in 0..32_u32 {
+        let chunk_sizes = (0_u32..).map(move |i| {
+            let hash = (i ^ seed.wrapping_mul(0x9E37_79B9)).wrapping_mul(2_654_435_761);
+            (hash >> 24) as usize % 64
+        });
+        assert_eq!(
+            decode_in_chunks(&input, chunk_sizes),
+            expected,
+            "seed {seed}"
+        );
+    }
+}
+
+#[test]
+fn whole_input_at_once() {
+    let input = input();
+    assert_eq!(
+        decode_in_chunks(&input, std::iter::once(input.len())),
+        expected_events(&input)
+    );
+}
+
+#[test]
+fn truncated_input() {
+    let input = input();
+    let mut decoder = Decoder::new();
+    decoder.push(&input[..input.len() - 1]);
+    while decoder.next_event().unwrap().is_some() {}
+    let err = decoder.finish().unwrap_err();
+    assert!(err.offset().is_some());
+}
+
+#[test]
+fn options_apply_to_yielded_items() {
+    let input = input();
+    let mut decoder = Decoder::with_options(&DecodeOptions {
+        limits: DecodeLimits {
+            max_locals: 100,
+            ..DecodeLimits::default()
+        },
+        ..DecodeOptions::default()
+    });
+    decoder.push(&input);
+    let mut bodies = Vec::new();
+    while let Some(event) = decoder.next_event().unwrap() {
+        if let Event::FuncBody(body) = event {
+            bodies.push(body);
+        }
+    }
+    decoder.finish().unwrap();
+    assert_eq!(bodies.len(), 3);
+    assert!(bodies[0].try_contents().is_ok());
+    assert!(is_limit_exceeded(
+        &bodies[1].try_contents().unwrap_err(),
+        Limit::Locals
+    ));
+    assert!(bodies[2].try_contents().is_ok());
+
+    let mut decoder = Decoder::with_options(&DecodeOptions {
+        limits: DecodeLimits {
+            max_sections: 1,
+            ..DecodeLimits::default()
+        },
+        ..DecodeOptions::default()
+    });
+    decoder.push(&input);
+    assert!(decoder.next_event().unwrap().is_some());
+    assert!(is_limit_exceeded(
+        &decoder.next_event().unwrap_err(),
+        Limit::Sections
+    ));
+}
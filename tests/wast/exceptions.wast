@@ -0,0 +1,49 @@
+;; Legacy exception handling instructions and the Tag section, in the syntax
+;; of the `wast` version we use, which still calls tags "events".
+
+(module
+  (import "env" "imported" (event $imported (param i64)))
+  (type $i32 (func (param i32)))
+  (event $empty)
+  (event $i32 (type $i32))
+  (export "i32" (event $i32))
+
+  (func (result i32)
+    (try (result i32)
+      (do (throw $i32 (i32.const 1)))
+      (catch $i32)
+      (catch $imported (drop) (i32.const 2))
+      (catch_all (i32.const 0))))
+
+  (func
+    (try
+      (do (throw $empty))
+      (catch $empty (rethrow 0))))
+
+  (func
+    (try
+      (do
+        (try
+          (do (throw $empty))
+          (delegate 0)))
+      (catch_all)))
+)
+
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\04\01\60\00\00" ;; Type section: () -> ()
+    "\0d\03\01\01\00" ;; Tag section: unknown attribute
+  )
+  "malformed tag attribute"
+)
+
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\04\01\60\00\00" ;; Type section: () -> ()
+    "\03\02\01\00" ;; Function section
+    "\0a\06\01\04\00\06\40\0b" ;; Code section: unterminated try
+  )
+  "unexpected end"
+)
@@ -0,0 +1,51 @@
+;; Typed function references. The `wast` version we use predates the final
+;; encoding of these types and instructions, so modules are given in binary.
+
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\01\10\03" ;; Type section
+  "\60\00\00" ;; () -> ()
+  "\60\01\64\00\00" ;; (ref 0) -> ()
+  "\60\01\63\00\01\64\00" ;; (ref null 0) -> (ref 0)
+  "\03\06\05\00\01\02\02\01" ;; Function section
+  "\0a\25\05" ;; Code section
+  "\02\00\0b"
+  "\06\00"
+  "\20\00" ;; local.get 0
+  "\14\00" ;; call_ref 0
+  "\0b"
+  "\0c\00"
+  "\02\64\00" ;; block (result (ref 0))
+  "\20\00" ;; local.get 0
+  "\d6\00" ;; br_on_non_null 0
+  "\d2\00" ;; ref.func 0
+  "\0b\0b"
+  "\05\00"
+  "\20\00" ;; local.get 0
+  "\d4" ;; ref.as_non_null
+  "\0b"
+  "\06\00"
+  "\20\00" ;; local.get 0
+  "\15\00" ;; return_call_ref 0
+  "\0b"
+)
+
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\01\04\01\60\00\00" ;; Type section: () -> ()
+  "\03\02\01\00" ;; Function section
+  "\0a\0c\01\0a\00" ;; Code section
+  "\02\40" ;; block
+  "\d0\70" ;; ref.null func
+  "\d5\00" ;; br_on_null 0
+  "\1a" ;; drop
+  "\0b\0b"
+)
+
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\01\06\01\60\01\64\40\00" ;; Type section: negative type index
+  )
+  "malformed heap type"
+)
@@ -0,0 +1,41 @@
+;; 64-bit memories and addresses. Offsets beyond 32 bits can't be expressed
+;; in the text format of the `wast` version we use, so they are tested in
+;; binary form.
+
+(module
+  (memory i64 1 2)
+  (data (i64.const 0) "\01\02\03\04")
+
+  (func (param $addr i64) (result i32)
+    (i32.load offset=4 align=2 (local.get $addr)))
+
+  (func (param $addr i64) (param $value i64)
+    (i64.store8 offset=1 (local.get $addr) (local.get $value)))
+
+  (func (result i64)
+    (drop (memory.grow (i64.const 1)))
+    (memory.size))
+)
+
+(module
+  (import "env" "memory" (memory i64 1))
+)
+
+(module binary
+  "\00asm" "\01\00\00\00"
+  "\01\05\01\60\00\01\7e" ;; Type section: () -> i64
+  "\03\02\01\00" ;; Function section
+  "\05\03\01\04\01" ;; Memory section: i64 memory with min 1
+  "\0a\0d\01\0b\00" ;; Code section
+  "\42\00" ;; i64.const 0
+  "\29\03\80\80\80\80\10" ;; i64.load offset=0x100000000
+  "\0b"
+)
+
+(assert_malformed
+  (module binary
+    "\00asm" "\01\00\00\00"
+    "\05\03\01\08\01" ;; Memory section: unknown limits flags
+  )
+  "malformed limits flags"
+)